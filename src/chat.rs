@@ -1,33 +1,424 @@
 use crate::defs::*;
-use crate::tools::{handle_read_fs, handle_search_fs};
-use crate::MODEL;
+use crate::throttle;
+use crate::tools;
+use crate::tools::{CommandTools, Progress, ToolError, Update};
+use crate::CONFIG;
 use bytes::Bytes;
+use futures_util::future::join_all;
+use google_ai_rs::genai::ResponseStream;
+use google_ai_rs::proto::candidate::FinishReason;
+use google_ai_rs::proto::GenerateContentResponse;
+use google_ai_rs::GenerativeModel;
 use hyper::body::Frame;
 use lazy_static::lazy_static;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::convert::Infallible;
 use std::fs;
-use tokio::sync::mpsc::Sender;
-use tokio::sync::Mutex;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::path::PathBuf;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::time::{sleep, timeout};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::sync::{oneshot, Mutex, MutexGuard};
+
+/// Waits longer than this on a full channel are reported as client backpressure
+const BACKPRESSURE_WARN: Duration = Duration::from_secs(1);
+
+/// Successful responses of read-only calls within one turn, by tool name and arguments
+type ToolCache = std::sync::Mutex<HashMap<String, FunctionResponse>>;
+
+/// Tool name and arguments of a call, identical for identical calls
+fn call_signature(call: &FunctionCall) -> String {
+    format!("{}:{}", call.name, serde_json::to_string(&call.args).unwrap())
+}
+
+/// The latest tool calls of a generation, as `YAS_LOOP_WINDOW` looks back over them
+struct CallLog {
+    window: Option<usize>,
+    /// Hash of `call_signature` and tool name of each, oldest first
+    recent: VecDeque<(u64, String)>,
+}
+
+impl CallLog {
+    fn new(window: Option<usize>) -> Self {
+        Self { window, recent: VecDeque::new() }
+    }
+
+    fn record(&mut self, call: &FunctionCall) {
+        let Some(window) = self.window else {
+            return;
+        };
+
+        let mut hasher = DefaultHasher::new();
+        call_signature(call).hash(&mut hasher);
+        self.recent.push_back((hasher.finish(), call.name.clone()));
+        if self.recent.len() > window {
+            self.recent.pop_front();
+        }
+    }
+
+    /// Tool names of the shortest run of calls the latest calls repeat, if they do: a single
+    /// call made three times in a row, or a longer run made twice.
+    ///
+    /// Each call has the same tool and arguments as the one a cycle earlier, so nothing but
+    /// their results could tell the model anything new.
+    fn cycle(&self) -> Option<Vec<&str>> {
+        let len = self.recent.len();
+        (1..=len / 2).find_map(|period| {
+            let repeats = if period == 1 { 3 } else { 2 };
+            if period * repeats > len {
+                return None;
+            }
+
+            let tail = len - period * repeats;
+            let repeating = (tail..len - period)
+                .all(|i| self.recent[i].0 == self.recent[i + period].0);
+            repeating.then(|| {
+                self.recent
+                    .range(len - period..)
+                    .map(|(_, name)| name.as_str())
+                    .collect()
+            })
+        })
+    }
+}
 
 lazy_static! {
-    static ref HISTORY: Mutex<Vec<Content>> = Mutex::new(load_history());
+    /// Locked only for moments, so reading history never waits on a slow client
+    static ref HISTORY: Mutex<Vec<Content>> = Mutex::new(load_history_or_seed());
+    /// Held by whatever writes history over time: a generation from its posted message to
+    /// its last turn, and anything replacing history, so neither interleaves with the other
+    static ref GENERATION: Mutex<()> = Mutex::new(());
+    /// Calls waiting on the user's decision, by call id
+    static ref APPROVALS: std::sync::Mutex<HashMap<String, oneshot::Sender<bool>>> =
+        std::sync::Mutex::new(HashMap::new());
+    /// Candidates besides the first from the latest generation, until one is chosen
+    static ref ALTERNATIVES: std::sync::Mutex<Option<Alternatives>> = std::sync::Mutex::new(None);
+    /// Latest function responses whole, for `GET /chat/tool-result/{id}`; call ids start over
+    /// with a new conversation, so it's emptied with history
+    static ref FULL_RESULTS: std::sync::Mutex<FullResults> = std::sync::Mutex::new(FullResults::default());
+}
+
+/// Most function responses `FULL_RESULTS` keeps, and most chars of JSON they may add up to;
+/// the oldest go first
+const MAX_FULL_RESULTS: usize = 64;
+const MAX_FULL_RESULTS_CHARS: usize = 16 * 1024 * 1024;
+
+/// Function responses as tools returned them, before history cuts them down
+#[derive(Default)]
+struct FullResults {
+    /// Each with its length in chars as JSON, oldest first
+    responses: VecDeque<(FunctionResponse, usize)>,
+    chars: usize,
+}
+
+impl FullResults {
+    fn push(&mut self, resp: FunctionResponse) {
+        let len = json_len(&resp);
+        self.responses.push_back((resp, len));
+        self.chars += len;
+
+        while self.responses.len() > MAX_FULL_RESULTS || self.chars > MAX_FULL_RESULTS_CHARS {
+            let Some((_, len)) = self.responses.pop_front() else {
+                break;
+            };
+            self.chars -= len;
+        }
+    }
+
+    fn get(&self, id: &str) -> Option<FunctionResponse> {
+        self.responses
+            .iter()
+            .rev()
+            .find(|(resp, _)| resp.id == id)
+            .map(|(resp, _)| resp.clone())
+    }
+
+    fn clear(&mut self) {
+        self.responses.clear();
+        self.chars = 0;
+    }
+}
+
+/// Tool calls run in this conversation, against `YAS_SESSION_MAX_TOOL_CALLS`
+static SESSION_TOOL_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// A turn to write history over time, held from a posted message to its generation's last turn
+pub struct Generation {
+    _turn: MutexGuard<'static, ()>,
+}
+
+impl Generation {
+    /// Waits for the running generation, and any queued before, to end
+    pub async fn wait() -> Self {
+        Self { _turn: GENERATION.lock().await }
+    }
+
+    /// `None` while a generation runs
+    pub fn try_begin() -> Option<Self> {
+        GENERATION.try_lock().ok().map(|turn| Self { _turn: turn })
+    }
+}
+
+/// Other candidates a generation streamed alongside the one put in history
+struct Alternatives {
+    /// Where the generation's content starts in history
+    start: usize,
+    /// Length of history once the generation ended; anything else means it has moved on
+    end: usize,
+    parts: BTreeMap<i32, Vec<Part>>,
+}
+
+/// How a stream of messages is delimited for the client
+#[derive(Clone, Copy)]
+pub enum Framing {
+    /// `text/event-stream`; messages are `data:` only, events are named
+    Sse,
+    /// `application/x-ndjson`; one JSON object per line, events as `{"event", "data"}`
+    Ndjson,
+}
+
+impl Framing {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Framing::Sse => "text/event-stream",
+            Framing::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    fn message<T: Serialize>(self, v: &T) -> Frame<Bytes> {
+        let json = serde_json::to_string(v).unwrap();
+        let frame = match self {
+            Framing::Sse => sse_frame(None, &json),
+            Framing::Ndjson => format!("{}\n", json),
+        };
+        Frame::data(Bytes::from(frame))
+    }
+
+    /// Like `message`, but as an event named `event` rather than a plain message
+    fn event<T: Serialize>(self, event: &str, v: &T) -> Frame<Bytes> {
+        let json = serde_json::to_string(v).unwrap();
+        let frame = match self {
+            Framing::Sse => sse_frame(Some(event), &json),
+            Framing::Ndjson => format!(
+                "{{\"event\":{},\"data\":{}}}\n",
+                serde_json::to_string(event).unwrap(),
+                json
+            ),
+        };
+        Frame::data(Bytes::from(frame))
+    }
+}
+
+/// An SSE frame carrying `data`, one `data:` line per line of it, as a named `event` if given.
+///
+/// JSON written by serde_json has no line breaks, but a line break in `data` would otherwise
+/// end the field early and leave the rest to be read as a field of its own.
+fn sse_frame(event: Option<&str>, data: &str) -> String {
+    let mut frame = String::with_capacity(data.len() + 16);
+    if let Some(event) = event {
+        frame.push_str(&format!("event: {}\n", event));
+    }
+    for line in data.split('\n') {
+        frame.push_str(&format!("data: {}\n", line.strip_suffix('\r').unwrap_or(line)));
+    }
+    frame.push('\n');
+    frame
+}
+
+/// Sending half of a response stream, with the framing the client asked for
+#[derive(Clone)]
+pub struct FrameSender {
+    sender: Sender<Result<Frame<Bytes>, Infallible>>,
+    framing: Framing,
 }
 
-fn frame_from_json<T: Serialize>(v: &T) -> Frame<Bytes> {
-    let json = serde_json::to_string(v).unwrap();
-    let sse_event = format!("data: {}\n\n", json);
-    Frame::data(Bytes::from(sse_event))
+impl FrameSender {
+    pub fn new(sender: Sender<Result<Frame<Bytes>, Infallible>>, framing: Framing) -> Self {
+        Self { sender, framing }
+    }
+
+    /// Tells an SSE client how long to wait before reconnecting; meant as the first frame.
+    /// Other framings have no such notion.
+    pub fn hint_retry(&self, retry: Duration) {
+        if let Framing::Sse = self.framing {
+            let frame = format!("retry: {}\n\n", retry.as_millis());
+            let _ = self.sender.try_send(Ok(Frame::data(Bytes::from(frame))));
+        }
+    }
 }
 
-async fn save_history() {
-    let v = HISTORY.lock().await;
-    let v = serde_json::to_vec(&*v).unwrap();
-    fs::write("history.json", v).unwrap()
+#[derive(Serialize)]
+struct ToolStarted {
+    id: String,
+    name: String,
+}
+
+#[derive(Serialize)]
+struct Throttled {
+    attempt: u32,
+    retry_in_ms: u64,
+}
+
+#[derive(Serialize)]
+struct CandidateChunk<'a> {
+    index: i32,
+    content: &'a Content,
+}
+
+#[derive(Serialize)]
+struct ApprovalRequest<'a> {
+    id: &'a str,
+    name: &'a str,
+    args: &'a Option<Struct>,
+}
+
+#[derive(Serialize)]
+struct ToolProgress {
+    id: String,
+    name: String,
+    progress: Struct,
+}
+
+#[derive(Serialize)]
+struct ToolOutput {
+    id: String,
+    name: String,
+    output: Struct,
+}
+
+/// Progress of a tool call, sent to the client as `progress` and `tool_output` events.
+///
+/// Status is best-effort: it's dropped rather than waited for when the client is behind.
+/// Output is waited for, holding the tool back, since a later part doesn't replace it.
+fn tool_progress(sender: &FrameSender, call: &FunctionCall) -> Progress {
+    let sender = sender.clone();
+    let (id, name) = (call.id.clone(), call.name.clone());
+
+    // Called on the tool's blocking thread, where waiting for the channel is fine
+    Progress::new(move |update| match update {
+        Update::Status(progress) => {
+            let progress = ToolProgress {
+                id: id.clone(),
+                name: name.clone(),
+                progress: progress.into(),
+            };
+            let _ = sender.sender.try_send(Ok(sender.framing.event("progress", &progress)));
+        }
+        Update::Output(output) => {
+            let output = ToolOutput {
+                id: id.clone(),
+                name: name.clone(),
+                output: output.into(),
+            };
+            let _ = sender.sender.blocking_send(Ok(sender.framing.event("tool_output", &output)));
+        }
+    })
+}
+
+/// Sends a frame, waiting for room if the channel is full.
+///
+/// Returns `false` once the receiving client has gone away.
+async fn send_frame(sender: &FrameSender, frame: Frame<Bytes>) -> bool {
+    let frame = match sender.sender.try_send(Ok(frame)) {
+        Ok(()) => return true,
+        Err(TrySendError::Closed(_)) => return false,
+        Err(TrySendError::Full(frame)) => frame,
+    };
+
+    let start = Instant::now();
+    let sent = sender.sender.send(frame).await.is_ok();
+
+    let waited = start.elapsed();
+    if waited >= BACKPRESSURE_WARN {
+        eprintln!(
+            "client backpressure: waited {:?} for room in a {}-frame channel",
+            waited,
+            sender.sender.max_capacity()
+        );
+    }
+
+    sent
+}
+
+/// Forwards frames of `receiver` for at most `max`, then ends with a system message if
+/// there's room for it.
+///
+/// Dropping `receiver` afterwards stops generation as a disconnected client would.
+pub fn limit_stream(
+    mut receiver: Receiver<Result<Frame<Bytes>, Infallible>>,
+    max: Duration,
+    framing: Framing,
+) -> Receiver<Result<Frame<Bytes>, Infallible>> {
+    let (sender, limited) = channel(receiver.max_capacity());
+    let sender = FrameSender::new(sender, framing);
+
+    tokio::spawn(async move {
+        let deadline = tokio::time::sleep(max);
+        tokio::pin!(deadline);
+
+        loop {
+            tokio::select! {
+                frame = receiver.recv() => {
+                    let Some(frame) = frame else {
+                        return;
+                    };
+                    // A client too slow to take frames is held no longer than any other
+                    tokio::select! {
+                        sent = sender.sender.send(frame) => if sent.is_err() {
+                            return;
+                        },
+                        _ = &mut deadline => break,
+                    }
+                }
+                _ = &mut deadline => break,
+            }
+        }
+
+        let chat = Content::system(vec![Part::new(Data::from(format!(
+            "Stream closed after reaching its limit of {:?}",
+            max
+        )))]);
+        // Best-effort: a client that stopped reading mustn't keep the stream open past its limit
+        let _ = sender.sender.try_send(Ok(sender.framing.message(&chat)));
+        drop(sender);
+    });
+
+    limited
+}
+
+/// Where history is saved
+#[cfg(not(test))]
+fn history_file() -> PathBuf {
+    PathBuf::from("history.json")
+}
+
+/// Tests keep their history out of the working directory
+#[cfg(test)]
+fn history_file() -> PathBuf {
+    std::env::temp_dir().join(format!("yas-test-{}-history.json", std::process::id()))
+}
+
+/// Replaces the saved history with `history` all at once, so a crash never leaves half a file
+fn write_history(history: &[Content]) {
+    let v = serde_json::to_vec(history).unwrap();
+    let file = history_file();
+    let tmp = file.with_extension("json.tmp");
+    fs::write(&tmp, v).unwrap();
+    fs::rename(&tmp, &file).unwrap()
+}
+
+pub async fn save_history() {
+    write_history(&HISTORY.lock().await)
 }
 
 fn load_history() -> Vec<Content> {
-    let s = match fs::read_to_string("history.json") {
+    let s = match fs::read_to_string(history_file()) {
         Ok(s) => s,
         Err(_) => return vec![],
     };
@@ -35,113 +426,1521 @@ fn load_history() -> Vec<Content> {
     serde_json::from_str(&s).unwrap_or_else(|_| vec![])
 }
 
+/// History of a new conversation, holding only the configured seed message if any
+fn new_conversation() -> Vec<Content> {
+    match &CONFIG.get().unwrap().seed_message {
+        Some(seed) => vec![Content::model(vec![Part::new(Data::from(seed.clone()))])],
+        None => vec![],
+    }
+}
+
+/// Loads saved history, or starts a new conversation with the configured seed message
+fn load_history_or_seed() -> Vec<Content> {
+    let history = load_history();
+    if !history.is_empty() {
+        return history;
+    }
+
+    new_conversation()
+}
+
+/// Starts a new conversation in memory and on disk alike, so a restart doesn't bring the
+/// old one back
+pub async fn clear_chat() {
+    let _generation = GENERATION.lock().await;
+    let mut history = HISTORY.lock().await;
+    *history = new_conversation();
+    write_history(&history);
+    SESSION_TOOL_CALLS.store(0, Ordering::Relaxed);
+    FULL_RESULTS.lock().unwrap().clear();
+    *ALTERNATIVES.lock().unwrap() = None;
+}
+
+/// Removes entry `index` from history, along with the entry it pairs with if it calls tools or
+/// answers such calls, since neither is valid without the other; `false` if there's no entry.
+///
+/// A user entry takes the tool calls made right after it, and their responses, with it: a
+/// function call is valid only after a user entry or a function response.
+pub async fn delete_message(index: usize) -> bool {
+    let _generation = GENERATION.lock().await;
+    let mut history = HISTORY.lock().await;
+    if index >= history.len() {
+        return false;
+    }
+
+    let calls_tools = |content: &Content| {
+        content.role == Role::Model
+            && content.parts.iter().any(|part| matches!(part.data, Some(Data::FunctionCall(_))))
+    };
+
+    let mut range = index..index + 1;
+    if calls_tools(&history[index]) && history.get(index + 1).is_some_and(|c| c.role == Role::Tool) {
+        range.end += 1;
+    } else if history[index].role == Role::Tool && index > 0 && calls_tools(&history[index - 1]) {
+        range.start -= 1;
+    } else if history[index].role == Role::User {
+        while history.get(range.end).is_some_and(calls_tools)
+            && history.get(range.end + 1).is_some_and(|c| c.role == Role::Tool)
+        {
+            range.end += 2;
+        }
+    }
+    history.drain(range);
+    drop(history);
+
+    // Where the latest candidates would go no longer lines up with history
+    *ALTERNATIVES.lock().unwrap() = None;
+
+    save_history().await;
+    true
+}
+
+/// Where `POST /chat/feedback` appends what it's given, one JSON object per line
+const FEEDBACK_LOG: &str = "feedback.jsonl";
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+#[derive(Serialize)]
+struct FeedbackEntry<'a> {
+    /// Seconds since the Unix epoch
+    time: u64,
+    index: usize,
+    rating: Rating,
+    comment: Option<&'a str>,
+    /// The rated entry as it was then, since history can be cleared or edited afterwards
+    content: &'a Content,
+}
+
+/// Why feedback can't be recorded
+pub enum FeedbackError {
+    /// History has no entry at that index
+    NotFound,
+    /// The entry isn't one the model wrote
+    NotModel,
+    Io(std::io::Error),
+}
+
+/// Appends a rating of model entry `index` to `FEEDBACK_LOG`; history itself is left as it is
+pub async fn record_feedback(index: usize, rating: Rating, comment: Option<&str>) -> Result<(), FeedbackError> {
+    let Some(content) = HISTORY.lock().await.get(index).cloned() else {
+        return Err(FeedbackError::NotFound);
+    };
+    if content.role != Role::Model {
+        return Err(FeedbackError::NotModel);
+    }
+
+    let entry = FeedbackEntry {
+        time: SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs(),
+        index,
+        rating,
+        comment,
+        content: &content,
+    };
+    let mut line = serde_json::to_vec(&entry).unwrap();
+    line.push(b'\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(FEEDBACK_LOG)
+        .await
+        .map_err(FeedbackError::Io)?;
+    file.write_all(&line).await.map_err(FeedbackError::Io)?;
+    // Until flushed, the write may still be underway, and its error unseen
+    file.flush().await.map_err(FeedbackError::Io)
+}
+
 pub async fn get_chat() -> Vec<Content> {
     HISTORY.lock().await.clone()
 }
 
-pub async fn add_chat(chat: Content) {
-    HISTORY.lock().await.push(chat);
+/// Streams every entry of history as it stands, one message each, then ends
+pub async fn replay_chat(sender: FrameSender) {
+    let history = HISTORY.lock().await.clone();
+
+    for content in &history {
+        if !send_frame(&sender, sender.framing.message(content)).await {
+            return;
+        }
+    }
+}
+
+/// Key pairing a function response with its call; ids first, names for calls saved without one
+fn call_key(id: &str, name: &str) -> String {
+    if id.is_empty() { name.to_string() } else { id.to_string() }
+}
+
+/// A problem with one entry of an imported history
+#[derive(Serialize)]
+pub struct InvalidEntry {
+    pub index: usize,
+    pub detail: String,
+}
+
+/// Problems with a part on its own, regardless of where it appears
+fn validate_part(part: &Part) -> Option<&'static str> {
+    match &part.data {
+        None => Some("part has no data"),
+        Some(Data::FunctionCall(call)) if call.name.is_empty() => Some("function call has no name"),
+        Some(Data::FunctionResponse(resp)) if resp.name.is_empty() => {
+            Some("function response has no name")
+        }
+        Some(Data::InlineData(blob)) if blob.mime_type.is_empty() => {
+            Some("inline data has no mime_type")
+        }
+        Some(Data::FileData(file)) if file.mime_type.is_empty() || file.file_uri.is_empty() => {
+            Some("file data needs both mime_type and file_uri")
+        }
+        _ => None,
+    }
+}
+
+/// Checks that `contents` is a history Gemini accepts: known roles, well-formed parts,
+/// and every function call answered by the tool entry right after it.
+///
+/// Reports every invalid entry rather than stopping at the first.
+fn validate_history(contents: &[Content]) -> Result<(), Vec<InvalidEntry>> {
+    let mut errors = vec![];
+    let mut error = |index: usize, detail: String| errors.push(InvalidEntry { index, detail });
+
+    let mut pending: Vec<String> = vec![];
+
+    for (i, content) in contents.iter().enumerate() {
+        if content.role == Role::System {
+            error(i, "role 'system' can't be in a conversation; expected user, model or tool".to_string());
+        }
+        if content.parts.is_empty() {
+            error(i, "entry has no parts".to_string());
+        }
+        if !pending.is_empty() && content.role != Role::Tool {
+            error(i, format!("function calls of entry {} have no responses", i - 1));
+            pending.clear();
+        }
+
+        let mut calls = vec![];
+        for (j, part) in content.parts.iter().enumerate() {
+            if let Some(detail) = validate_part(part) {
+                error(i, format!("part {}: {}", j, detail));
+                continue;
+            }
+
+            match &part.data {
+                Some(Data::FunctionCall(call)) if content.role == Role::Model => {
+                    calls.push(call_key(&call.id, &call.name));
+                }
+                Some(Data::FunctionCall(_)) => {
+                    error(i, format!("part {}: function call in a '{}' entry", j, content.role));
+                }
+                Some(Data::FunctionResponse(resp)) if content.role == Role::Tool => {
+                    let key = call_key(&resp.id, &resp.name);
+                    match pending.iter().position(|k| *k == key) {
+                        Some(at) => {
+                            pending.remove(at);
+                        }
+                        None => error(i, format!("part {}: response to '{}' answers no call", j, key)),
+                    }
+                }
+                Some(Data::FunctionResponse(_)) => {
+                    error(i, format!("part {}: function response in a '{}' entry", j, content.role));
+                }
+                _ => {}
+            }
+        }
+
+        if content.role == Role::Tool && !pending.is_empty() {
+            error(i, format!("missing responses to {}", pending.join(", ")));
+        }
+        pending = calls;
+    }
+
+    if !pending.is_empty() {
+        error(contents.len() - 1, "function calls have no responses".to_string());
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors),
+    }
 }
 
-async fn process_chat_once(sender: &Sender<Result<Frame<Bytes>, Infallible>>) -> bool {
+/// Replaces history with `contents`, or appends them with `append`, once they validate
+pub async fn import_chat(contents: Vec<Content>, append: bool) -> Result<usize, Vec<InvalidEntry>> {
+    validate_history(&contents)?;
+
+    let _generation = GENERATION.lock().await;
     let mut history = HISTORY.lock().await;
+    if !append {
+        history.clear();
+        SESSION_TOOL_CALLS.store(0, Ordering::Relaxed);
+        FULL_RESULTS.lock().unwrap().clear();
+    }
+    history.extend(contents);
+    let len = history.len();
+    drop(history);
 
-    let contents_copy = history
-        .iter()
-        .cloned()
+    // Even at the same length, history is no longer what the candidates were generated for
+    *ALTERNATIVES.lock().unwrap() = None;
+
+    save_history().await;
+    Ok(len)
+}
+
+/// Content of `YAS_CONTEXT_FILES` as it is now, as one user message; unreadable files are skipped
+async fn context_content() -> Option<Content> {
+    let paths = CONFIG.get().unwrap().context_files.clone();
+
+    let mut text = String::new();
+    for path in &paths {
+        match tokio::fs::read_to_string(path).await {
+            Ok(content) => {
+                let newline = if content.ends_with('\n') { "" } else { "\n" };
+                text.push_str(&format!("Content of '{}':\n{}{}\n", path.display(), content, newline));
+            }
+            Err(e) => eprintln!("warn: cannot read context file '{}': {}", path.display(), e),
+        }
+    }
+
+    if text.is_empty() {
+        return None;
+    }
+
+    let text = format!("Project files given as context for this conversation follow.\n\n{}", text);
+    Some(Content {
+        parts: vec![Part::new(Data::from(text))],
+        role: Role::User,
+    })
+}
+
+/// Tells the model tools are off once the conversation used up `YAS_SESSION_MAX_TOOL_CALLS`,
+/// as a user message following history
+fn tool_cap_content() -> Option<Content> {
+    let max = CONFIG.get().unwrap().session_max_tool_calls?;
+    if SESSION_TOOL_CALLS.load(Ordering::Relaxed) < max {
+        return None;
+    }
+
+    let text = format!(
+        "[System notice] This conversation used up the {} tool calls it may make, so any more are refused; answer without tools",
+        max
+    );
+    Some(Content {
+        parts: vec![Part::new(Data::from(text))],
+        role: Role::User,
+    })
+}
+
+/// What replies are generated by: the model, or a script standing in for it in tests
+pub trait Model {
+    type Stream: Responses;
+
+    async fn stream(&self, contents: Vec<google_ai_rs::Content>) -> Result<Self::Stream, google_ai_rs::Error>;
+}
+
+/// Responses of a reply being generated, in order
+pub trait Responses {
+    async fn next(&mut self) -> Result<Option<GenerateContentResponse>, google_ai_rs::Error>;
+}
+
+impl Model for GenerativeModel<'_> {
+    type Stream = ResponseStream;
+
+    async fn stream(&self, contents: Vec<google_ai_rs::Content>) -> Result<ResponseStream, google_ai_rs::Error> {
+        self.stream_generate_content(contents).await
+    }
+}
+
+impl Responses for ResponseStream {
+    async fn next(&mut self) -> Result<Option<GenerateContentResponse>, google_ai_rs::Error> {
+        ResponseStream::next(self).await
+    }
+}
+
+/// Starts streaming the model's reply to `contents`, with its first response.
+///
+/// While the API is throttling, waits as long as it asks (or twice as long each time if it
+/// doesn't say) and tries again, until the waits would add up past `YAS_THROTTLE_MAX_WAIT_SECS`,
+/// telling the client with a `throttled` event before each wait. A throttled request can fail
+/// as late as its first response, so that's read here too.
+async fn start_stream<M: Model>(
+    sender: &FrameSender,
+    model: &M,
+    contents: Vec<google_ai_rs::Content>,
+) -> Result<(M::Stream, Option<GenerateContentResponse>), google_ai_rs::Error> {
+    let max_wait = CONFIG.get().unwrap().throttle_max_wait;
+    let mut waited = Duration::ZERO;
+    let mut attempt = 0;
+
+    loop {
+        let error = match model.stream(contents.clone()).await {
+            Ok(mut stream) => match stream.next().await {
+                Ok(first) => return Ok((stream, first)),
+                Err(e) => e,
+            },
+            Err(e) => e,
+        };
+        if !throttle::is_throttled(&error) {
+            return Err(error);
+        }
+
+        let delay = throttle::retry_after(&error).unwrap_or(Duration::from_secs(1 << attempt.min(6)));
+        if waited + delay > max_wait {
+            return Err(error);
+        }
+
+        attempt += 1;
+        let throttled = Throttled {
+            attempt,
+            retry_in_ms: delay.as_millis() as u64,
+        };
+        if !send_frame(sender, sender.framing.event("throttled", &throttled)).await {
+            return Err(error);
+        }
+
+        sleep(delay).await;
+        waited += delay;
+    }
+}
+
+/// One turn of a generation; the caller holds a `Generation`.
+///
+/// History is locked only to copy it and to append to it, never while waiting on the
+/// model or the client, so a slow client holds back its own generation alone.
+async fn process_chat_once<M: Model>(
+    sender: &FrameSender,
+    model: &M,
+    commands: &CommandTools,
+    cache: &ToolCache,
+    log: &mut CallLog,
+) -> bool {
+    // Context and notices go only into requests, so history and `GET /chat` never carry them
+    let context = context_content().await;
+
+    let mut history = HISTORY.lock().await;
+    let contents_copy = context
+        .into_iter()
+        .chain(history.iter().cloned())
+        .chain(tool_cap_content())
         .map(Into::into)
         .collect::<Vec<google_ai_rs::Content>>();
 
-    let mut response_stream = match MODEL
-        .get()
-        .unwrap()
-        .stream_generate_content(contents_copy)
-        .await {
-        Ok(stream) => stream,
+    // The request carries full copies, so what it sends can be cut down in history
+    if let Some(max) = CONFIG.get().unwrap().max_tool_result_chars {
+        for content in history.iter_mut().filter(|c| c.role == Role::Tool) {
+            truncate_tool_results(content, max);
+        }
+    }
+
+    let start = history.len();
+    drop(history);
+
+    let (mut response_stream, first) = match start_stream(sender, model, contents_copy).await {
+        Ok(started) => started,
         Err(e) => {
             let chat = Content::system(vec![
                 Part::new(Data::from(format!("Error while generating stream content: {:?}", e)))
             ]);
-            let _ = sender.send(Ok(frame_from_json(&chat))).await;
+            send_frame(sender, sender.framing.message(&chat)).await;
             return false;
         }
     };
 
     let mut function_called = false;
+    let mut alternatives: BTreeMap<i32, Vec<Part>> = BTreeMap::new();
 
-    while let Some(resp) = match response_stream.next().await {
-        Ok(part) => part,
-        Err(e) => {
-            let chat = Content::system(vec![
-                Part::new(Data::from(format!("Error while iterating stream: {:?}", e)))
-            ]);
-            let _ = sender.send(Ok(frame_from_json(&chat))).await;
-            return false;
+    let mut first = Some(first);
+    loop {
+        let next = match first.take() {
+            Some(first) => Ok(first),
+            None => response_stream.next().await,
+        };
+        let resp = match next {
+            Ok(Some(resp)) => resp,
+            Ok(None) => break,
+            Err(e) => {
+                let chat = Content::system(vec![
+                    Part::new(Data::from(format!("Error while iterating stream: {:?}", e)))
+                ]);
+                send_frame(sender, sender.framing.message(&chat)).await;
+                return false;
+            }
+        };
+
+        // Only the first candidate goes into history and gets its tool calls run
+        for candidate in resp.candidates.iter().filter(|c| c.index.unwrap_or(0) != 0) {
+            let Some(content) = &candidate.content else {
+                continue;
+            };
+            let content: Content = content.clone().into();
+            let index = candidate.index.unwrap_or(0);
+
+            let chunk = CandidateChunk { index, content: &content };
+            send_frame(sender, sender.framing.event("candidate", &chunk)).await;
+            alternatives.entry(index).or_default().extend(content.parts);
         }
-    } {
-        let Some(candidate) = resp.candidates.first() else {
+
+        let Some(candidate) = resp.candidates.iter().find(|c| c.index.unwrap_or(0) == 0) else {
             continue;
         };
 
-        if candidate.finish_reason != /* STOP */ 1 && candidate.finish_reason != /* NONE */ 0 {
+        let failure = match FinishReason::try_from(candidate.finish_reason) {
+            Ok(FinishReason::Unspecified | FinishReason::Stop) => None,
+            Ok(reason) => Some(reason.as_str_name().to_string()),
+            Err(_) if CONFIG.get().unwrap().strict_finish_reason => {
+                Some(format!("code {}", candidate.finish_reason))
+            }
+            Err(_) => {
+                // Likely added to the API after this was written; the content may still be fine
+                eprintln!("warn: unknown finish reason {}; continuing", candidate.finish_reason);
+                None
+            }
+        };
+
+        if let Some(failure) = failure {
             let chat = Content::system(vec![
-                Part::new(Data::from(format!("Generation failed with code: {:}", candidate.finish_reason)))
+                Part::new(Data::from(format!("Generation failed: {}", failure)))
             ]);
-            let _ = sender.send(Ok(frame_from_json(&chat))).await;
+            send_frame(sender, sender.framing.message(&chat)).await;
             return false;
         }
 
         let Some(content) = &candidate.content else {
             continue;
         };
-        let content: Content = content.clone().into();
-
-        history.push(content.clone().into());
+        let mut content: Content = content.clone().into();
+        {
+            let mut history = HISTORY.lock().await;
+            assign_call_ids(&mut content, history.len());
+            history.push(content.clone());
+        }
 
-        let _ = sender.send(Ok(frame_from_json(&content))).await;
+        // A disconnected client stops generation, but tool calls already in
+        // history still get their responses so the history stays valid
+        let mut connected = send_frame(sender, sender.framing.message(&content)).await;
 
         let mut function_responses: Vec<Part> = Vec::new();
 
-        for part in content.parts {
-            let Some(data) = part.data else {
-                continue;
-            };
+        let mut calls = content
+            .parts
+            .into_iter()
+            .filter_map(|part| match part.data {
+                Some(Data::FunctionCall(call)) => Some(call),
+                _ => None,
+            })
+            .peekable();
 
-            if let Data::FunctionCall(call) = data {
-                function_called = true;
+        // Runs of read-only calls run together; anything with side effects runs alone, in order
+        while let Some(call) = calls.next() {
+            function_called = true;
 
-                match handle_function_call(call).await {
-                    Ok(resp) => {
-                        function_responses.push(Part::new(Data::FunctionResponse(resp)))
-                    }
-                    Err(e) => {
-                        function_responses.push(Part::new(Data::from(e)))
-                    }
-                };
+            let mut batch = vec![call];
+            if tools::is_read_only(&batch[0].name) {
+                while let Some(call) = calls.next_if(|call| tools::is_read_only(&call.name)) {
+                    batch.push(call);
+                }
+            } else {
+                // What was read before may not be what's there after
+                cache.lock().unwrap().clear();
             }
+
+            for call in &batch {
+                log.record(call);
+            }
+
+            let resps = join_all(batch.into_iter().map(|call| handle_function_call(sender, call, commands, cache))).await;
+            function_responses.extend(
+                resps
+                    .into_iter()
+                    .map(|resp| Part::new(Data::FunctionResponse(resp))),
+            );
         }
 
         if !function_responses.is_empty() {
-            let function_response_content = Content::tool(function_responses);
-            let _ = sender.send(Ok(frame_from_json(&function_response_content))).await;
-            history.push(function_response_content);
+            let mut function_response_content = Content::tool(function_responses);
+            let frame = match CONFIG.get().unwrap().tool_preview_chars {
+                Some(max) => sender.framing.message(&preview_tool_results(&function_response_content, max)),
+                None => sender.framing.message(&function_response_content),
+            };
+            remove_display(&mut function_response_content);
+            {
+                let mut full = FULL_RESULTS.lock().unwrap();
+                for part in &function_response_content.parts {
+                    if let Some(Data::FunctionResponse(resp)) = &part.data {
+                        full.push(resp.clone());
+                    }
+                }
+            }
+            HISTORY.lock().await.push(function_response_content);
+
+            connected = connected && send_frame(sender, frame).await;
+        }
+
+        if !connected {
+            return false;
+        }
+
+        if let Some(cycle) = log.cycle() {
+            let chat = Content::system(vec![
+                Part::new(Data::from(format!(
+                    "Generation stopped: the model kept repeating the same {} with the same arguments ({})",
+                    if cycle.len() == 1 { "tool call" } else { "tool calls" },
+                    cycle.join(", "),
+                )))
+            ]);
+            send_frame(sender, sender.framing.message(&chat)).await;
+            return false;
         }
     }
 
+    if !alternatives.is_empty() {
+        *ALTERNATIVES.lock().unwrap() = Some(Alternatives {
+            start,
+            end: HISTORY.lock().await.len(),
+            parts: alternatives,
+        });
+    }
+
     function_called
 }
 
-async fn handle_function_call(call: FunctionCall) -> Result<FunctionResponse, String> {
-    match call.name.as_str() {
-        "search_fs" => Ok(handle_search_fs(call.into()).into()),
-        "read_fs" => Ok(handle_read_fs(call.into()).into()),
-        _ => Err(format!("Unknown function '{}'", call.name)),
+/// Why a candidate can't be chosen
+pub enum ChooseError {
+    /// No alternatives are pending, or none has that index
+    NotFound,
+    /// History changed since the candidates were generated
+    Stale,
+    /// The candidate calls tools, which were never run
+    CallsTools,
+}
+
+/// Replaces what the latest generation put in history with its candidate `index`
+pub async fn choose_candidate(index: i32) -> Result<(), ChooseError> {
+    let _generation = GENERATION.lock().await;
+    let mut history = HISTORY.lock().await;
+
+    let (start, parts) = {
+        let mut pending = ALTERNATIVES.lock().unwrap();
+        let Some(alternatives) = pending.as_ref() else {
+            return Err(ChooseError::NotFound);
+        };
+        if alternatives.end != history.len() {
+            return Err(ChooseError::Stale);
+        }
+        let Some(parts) = alternatives.parts.get(&index) else {
+            return Err(ChooseError::NotFound);
+        };
+        if parts.iter().any(|part| matches!(part.data, Some(Data::FunctionCall(_)))) {
+            return Err(ChooseError::CallsTools);
+        }
+
+        let chosen = (alternatives.start, parts.clone());
+        *pending = None;
+        chosen
+    };
+
+    history.truncate(start);
+    history.push(Content::model(parts));
+    drop(history);
+
+    save_history().await;
+    Ok(())
+}
+
+fn error_response(id: String, name: String, error: ToolError) -> FunctionResponse {
+    FunctionResponse {
+        id,
+        name,
+        response: Some(prost_types::Struct::from(error).into()),
+    }
+}
+
+/// Length in chars of `v` as JSON
+fn json_len<T: Serialize>(v: &T) -> usize {
+    serde_json::to_string(v).unwrap().chars().count()
+}
+
+fn omitted(note: String) -> Value {
+    Value {
+        kind: Some(Kind::StringValue(note)),
+    }
+}
+
+/// Cuts strings and lists within `value` down to about `budget` chars, leaving it valid
+fn truncate_value(value: &mut Value, budget: usize) {
+    match &mut value.kind {
+        Some(Kind::StringValue(s)) => {
+            let len = s.chars().count();
+            // The note takes room too, and a value is never made longer by it
+            let note = format!("... [{} chars omitted]", len).chars().count();
+            let keep = budget.saturating_sub(note);
+            if len > budget && keep + note < len {
+                let head: String = s.chars().take(keep).collect();
+                *s = format!("{}... [{} chars omitted]", head, len - keep);
+            }
+        }
+        Some(Kind::ListValue(list)) => {
+            let len = list.values.len();
+            let mut used = 2 + json_len(&omitted(format!("... [{} items omitted]", len))) + 1;
+            let mut keep = 0;
+            for v in &list.values {
+                used += json_len(v) + 1;
+                if used > budget {
+                    break;
+                }
+                keep += 1;
+            }
+
+            // Rather part of one item than none at all
+            if keep == 0 && len > 0 {
+                keep = 1;
+                truncate_value(&mut list.values[0], budget);
+            }
+            if keep < len {
+                list.values.truncate(keep);
+                list.values.push(omitted(format!("... [{} items omitted]", len - keep)));
+            }
+        }
+        Some(Kind::StructValue(s)) => truncate_fields(&mut s.fields, budget),
+        _ => {}
+    }
+}
+
+/// Cuts the largest fields first until `fields` fits in about `max` chars as JSON
+fn truncate_fields(fields: &mut BTreeMap<String, Value>, max: usize) {
+    let mut keys: Vec<(String, usize)> = fields
+        .iter()
+        .filter(|(k, _)| *k != tools::TOOL_FIELD && *k != tools::CALL_ID_FIELD)
+        .map(|(k, v)| (k.clone(), json_len(v)))
+        .collect();
+    keys.sort_by_key(|(_, len)| std::cmp::Reverse(*len));
+
+    for (key, len) in keys {
+        let total = json_len(fields);
+        if total <= max {
+            break;
+        }
+
+        let rest = total - len;
+        truncate_value(fields.get_mut(&key).unwrap(), max.saturating_sub(rest));
     }
 }
 
-pub async fn process_chat(sender: Sender<Result<Frame<Bytes>, Infallible>>) {
-    while process_chat_once(&sender).await {
+/// What marking a response `truncated` adds to its JSON
+const TRUNCATED_MARKER: &str = ",\"truncated\":true";
+
+/// Cuts down each function response longer than `max` chars as JSON, field by field,
+/// so what remains is still a well-formed response with its long values shortened.
+///
+/// Results that were already truncated are left alone.
+fn truncate_tool_results(content: &mut Content, max: usize) {
+    for part in &mut content.parts {
+        let Some(Data::FunctionResponse(resp)) = &mut part.data else {
+            continue;
+        };
+        let Some(response) = &mut resp.response else {
+            continue;
+        };
+        if response.fields.contains_key("truncated") || json_len(response) <= max {
+            continue;
+        }
+
+        truncate_fields(&mut response.fields, max.saturating_sub(TRUNCATED_MARKER.len()));
+        response.fields.insert(
+            "truncated".to_string(),
+            Value {
+                kind: Some(Kind::BoolValue(true)),
+            },
+        );
+    }
+}
+
+/// Copy of `content` for the client, with function responses longer than `max` chars as JSON
+/// cut down as in `truncate_tool_results`, each marked with a `handle` to fetch it whole.
+///
+/// What's for display alone is neither counted nor cut, as it isn't the result itself.
+fn preview_tool_results(content: &Content, max: usize) -> Content {
+    let mut preview = content.clone();
+    for part in &mut preview.parts {
+        let Some(Data::FunctionResponse(resp)) = &mut part.data else {
+            continue;
+        };
+        let handle = resp.id.clone();
+        let Some(response) = &mut resp.response else {
+            continue;
+        };
+
+        let display = response.fields.remove(tools::DISPLAY_FIELD);
+        if json_len(response) > max {
+            truncate_fields(&mut response.fields, max);
+            response.fields.insert(
+                "truncated".to_string(),
+                Value {
+                    kind: Some(Kind::BoolValue(true)),
+                },
+            );
+            response.fields.insert(
+                "handle".to_string(),
+                Value {
+                    kind: Some(Kind::StringValue(handle)),
+                },
+            );
+        }
+        if let Some(display) = display {
+            response.fields.insert(tools::DISPLAY_FIELD.to_string(), display);
+        }
+    }
+
+    preview
+}
+
+/// Drops what tools returned for the user's eyes alone, before `content` goes into history
+fn remove_display(content: &mut Content) {
+    for part in &mut content.parts {
+        if let Some(Data::FunctionResponse(FunctionResponse { response: Some(response), .. })) = &mut part.data {
+            response.fields.remove(tools::DISPLAY_FIELD);
+        }
+    }
+}
+
+/// The latest function response to call `id` whole, as the tool returned it; once it's no
+/// longer kept, as history keeps it, marked `truncated` if cut down
+pub async fn get_tool_result(id: &str) -> Option<FunctionResponse> {
+    if let Some(resp) = FULL_RESULTS.lock().unwrap().get(id) {
+        return Some(resp);
+    }
+
+    HISTORY
+        .lock()
+        .await
+        .iter()
+        .rev()
+        .filter(|content| content.role == Role::Tool)
+        .flat_map(|content| &content.parts)
+        .find_map(|part| match &part.data {
+            Some(Data::FunctionResponse(resp)) if resp.id == id => Some(resp.clone()),
+            _ => None,
+        })
+}
+
+/// Gives each function call without an id one derived from its position in history.
+///
+/// Gemini matches responses to calls by id, so every call needs a distinct one.
+fn assign_call_ids(content: &mut Content, index: usize) {
+    for (i, part) in content.parts.iter_mut().enumerate() {
+        if let Some(Data::FunctionCall(call)) = &mut part.data
+            && call.id.is_empty()
+        {
+            call.id = format!("call_{}_{}", index, i);
+        }
+    }
+}
+
+/// Settles the pending approval of call `id`; `false` if no call is waiting on it
+pub fn approve(id: &str, approved: bool) -> bool {
+    match APPROVALS.lock().unwrap().remove(id) {
+        Some(decision) => decision.send(approved).is_ok(),
+        None => false,
+    }
+}
+
+/// Asks the client to approve `call` and waits for `approve`; a reason to refuse it if not approved
+async fn request_approval(sender: &FrameSender, call: &FunctionCall, wait: Duration) -> Option<String> {
+    let (decision, decided) = oneshot::channel();
+    APPROVALS.lock().unwrap().insert(call.id.clone(), decision);
+
+    let request = ApprovalRequest {
+        id: &call.id,
+        name: &call.name,
+        args: &call.args,
+    };
+    let denial = if !send_frame(sender, sender.framing.event("approval_request", &request)).await {
+        Some("Denied: nobody is connected to approve it".to_string())
+    } else {
+        match timeout(wait, decided).await {
+            Ok(Ok(true)) => None,
+            Ok(_) => Some("Denied by user".to_string()),
+            Err(_) => Some(format!("Denied: not approved within {:?}", wait)),
+        }
+    };
+
+    APPROVALS.lock().unwrap().remove(&call.id);
+    denial
+}
+
+/// Runs a function call; the response always carries the call's id and name
+async fn run_function_call(sender: &FrameSender, call: FunctionCall, commands: &CommandTools) -> FunctionResponse {
+    let config = CONFIG.get().unwrap();
+    let (id, name) = (call.id.clone(), call.name.clone());
+
+    if config.no_tools {
+        return error_response(id, name, ToolError::Denied("Tools are disabled".to_string()));
+    }
+
+    let Some(handler) = tools::handler(&config, commands, &call.name) else {
+        return error_response(id, name.clone(), ToolError::UnknownTool(format!("Unknown function '{}'", name)));
+    };
+
+    if let Some(max) = config.session_max_tool_calls {
+        let calls = SESSION_TOOL_CALLS.fetch_add(1, Ordering::Relaxed);
+        if calls == max {
+            let chat = Content::system(vec![Part::new(Data::from(format!(
+                "This conversation used up its {} tool calls; tools are off until it's cleared",
+                max
+            )))]);
+            send_frame(sender, sender.framing.message(&chat)).await;
+        }
+        if calls >= max {
+            return error_response(id, name, ToolError::Denied(format!(
+                "This conversation has used all {} tool calls it may make; answer without tools",
+                max
+            )));
+        }
+    }
+
+    if config.approve_tools
+        && let Some(denial) = request_approval(sender, &call, config.approval_timeout).await
+    {
+        return error_response(id, name, ToolError::Denied(denial));
+    }
+
+    let started = ToolStarted {
+        id: id.clone(),
+        name: name.clone(),
+    };
+    send_frame(sender, sender.framing.event("tool_started", &started)).await;
+    let progress = tool_progress(sender, &call);
+
+    // Tools do blocking filesystem work, so they run off the async runtime.
+    // A timed-out tool can't be cancelled; its thread is left to finish on its own.
+    let task = tokio::task::spawn_blocking(move || handler.handle(call.into(), &progress));
+
+    match timeout(config.tool_timeout, task).await {
+        Ok(Ok(resp)) => FunctionResponse {
+            id,
+            name,
+            ..resp.into()
+        },
+        Ok(Err(e)) => error_response(id, name, ToolError::ExecutionFailed(format!("Tool failed: {}", e))),
+        Err(_) => error_response(
+            id,
+            name,
+            ToolError::Timeout(format!("Tool timed out after {:?}", config.tool_timeout)),
+        ),
+    }
+}
+
+/// Runs a read-only call only if an identical one hasn't succeeded yet this turn
+async fn run_cached_function_call(
+    sender: &FrameSender,
+    call: FunctionCall,
+    commands: &CommandTools,
+    cache: &ToolCache,
+) -> FunctionResponse {
+    if !tools::is_read_only(&call.name) {
+        return run_function_call(sender, call, commands).await;
+    }
+
+    let key = call_signature(&call);
+    if let Some(resp) = cache.lock().unwrap().get(&key) {
+        return FunctionResponse {
+            id: call.id,
+            ..resp.clone()
+        };
+    }
+
+    let resp = run_function_call(sender, call, commands).await;
+    let failed = resp
+        .response
+        .as_ref()
+        .is_none_or(|response| response.fields.contains_key("error"));
+    if !failed {
+        cache.lock().unwrap().insert(key, resp.clone());
+    }
+
+    resp
+}
+
+/// Runs a function call, naming the tool and call id inside the response as well
+async fn handle_function_call(
+    sender: &FrameSender,
+    call: FunctionCall,
+    commands: &CommandTools,
+    cache: &ToolCache,
+) -> FunctionResponse {
+    let mut resp = run_cached_function_call(sender, call, commands, cache).await;
+
+    let response = resp.response.get_or_insert_with(|| Struct {
+        fields: BTreeMap::new(),
+    });
+    for (field, value) in [(tools::TOOL_FIELD, &resp.name), (tools::CALL_ID_FIELD, &resp.id)] {
+        response.fields.insert(
+            field.to_string(),
+            Value {
+                kind: Some(Kind::StringValue(value.clone())),
+            },
+        );
+    }
+
+    resp
+}
+
+/// Appends the posted `chat` to history, then generates replies until the model stops
+/// calling tools, streaming each turn.
+///
+/// A `POST /chat` stream carries, per turn and in order:
+///
+/// - a `throttled` event each time the API rate-limits the turn's request and it's retried;
+/// - a message for each model content as it arrives, in the form history keeps it;
+/// - with `YAS_APPROVE_TOOLS`, an `approval_request` event for each of its function calls,
+///   answered through `POST /chat/approve`;
+/// - a `tool_started` event for each of its function calls as it starts running, once
+///   allowed, so never for a denied one, then `progress` events while they run (best-effort, possibly dropped) and `tool_output` events with parts of their
+///   results as they're found;
+/// - one `tool` message with every function response to that content, those longer than
+///   `YAS_TOOL_PREVIEW_CHARS` cut down and given a `handle` for `GET /chat/tool-result/{handle}`.
+///
+/// Within one turn, a read-only tool call identical to one that already succeeded gets the
+/// same response without running again, until a tool with side effects runs.
+///
+/// With `Accept: application/x-ndjson` the same frames come one per line, events wrapped as
+/// `{"event": name, "data": ...}`.
+///
+/// A `system` message ends the stream early on errors or once the model keeps repeating the
+/// same tool calls within `YAS_LOOP_WINDOW` of them, or tells without ending it that the
+/// conversation used up `YAS_SESSION_MAX_TOOL_CALLS`. Nothing already in history — the
+/// posted message included — is sent again, so a client appends frames to what it has.
+///
+/// A client reading slowly slows generation down: frames wait for room in the channel, and
+/// the model's stream isn't read meanwhile. Generations queue behind each other, each holding
+/// its `Generation` (unless `YAS_REJECT_BUSY` turns the later ones away), but reads of history
+/// don't wait for them.
+pub async fn process_chat<M: Model>(_generation: Generation, chat: Content, sender: FrameSender, model: &M) {
+    // The client already has what it posted, so it isn't streamed back
+    HISTORY.lock().await.push(chat);
+
+    // Command tools reloaded meanwhile are left to later generations, like the model is
+    let commands = CommandTools::current();
+    let cache = ToolCache::default();
+    let mut log = CallLog::new(CONFIG.get().unwrap().loop_window);
+    while process_chat_once(&sender, model, &commands, &cache, &mut log).await {
     }
 
     save_history().await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{init_config, temp_dir};
+    use std::path::Path;
+
+    fn frame_text(frame: Frame<Bytes>) -> String {
+        String::from_utf8(frame.into_data().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn sse_message_is_one_data_line() {
+        let frame = Framing::Sse.message(&serde_json::json!({"text": "a\nb"}));
+        assert_eq!(frame_text(frame), "data: {\"text\":\"a\\nb\"}\n\n");
+    }
+
+    #[test]
+    fn sse_event_names_the_event() {
+        let frame = Framing::Sse.event("progress", &serde_json::json!({"scanned": 1}));
+        assert_eq!(frame_text(frame), "event: progress\ndata: {\"scanned\":1}\n\n");
+    }
+
+    fn response(id: &str, result: &str) -> FunctionResponse {
+        FunctionResponse {
+            id: id.to_string(),
+            name: "read_fs".to_string(),
+            response: Some(Struct {
+                fields: BTreeMap::from([(
+                    "result".to_string(),
+                    Value {
+                        kind: Some(Kind::StringValue(result.to_string())),
+                    },
+                )]),
+            }),
+        }
+    }
+
+    #[test]
+    fn full_results_keep_the_latest_whole() {
+        let mut full = FullResults::default();
+        for i in 0..MAX_FULL_RESULTS + 1 {
+            full.push(response(&format!("call_{}", i), "short"));
+        }
+        full.push(response("call_1", &"x".repeat(1000)));
+
+        assert!(full.get("call_0").is_none());
+        assert_eq!(full.responses.len(), MAX_FULL_RESULTS);
+        assert_eq!(json_len(&full.get("call_1").unwrap().response), 1000 + r#"{"result":""}"#.len());
+    }
+
+    /// A log of calls to tools named as given, each with argument `x` set to the value given
+    fn call_log(calls: &[(&str, &str)]) -> CallLog {
+        let mut log = CallLog::new(Some(12));
+        for (name, x) in calls {
+            log.record(&FunctionCall {
+                id: String::new(),
+                name: name.to_string(),
+                args: Some(Struct {
+                    fields: BTreeMap::from([("x".to_string(), string_value(x))]),
+                }),
+            });
+        }
+        log
+    }
+
+    #[test]
+    fn call_made_three_times_in_a_row_is_a_cycle() {
+        assert_eq!(call_log(&[("a", "1"), ("a", "1")]).cycle(), None);
+        assert_eq!(call_log(&[("b", "1"), ("a", "1"), ("a", "1"), ("a", "1")]).cycle(), Some(vec!["a"]));
+    }
+
+    #[test]
+    fn run_of_calls_made_twice_is_a_cycle() {
+        let log = call_log(&[("a", "1"), ("b", "2"), ("a", "1"), ("b", "2")]);
+        assert_eq!(log.cycle(), Some(vec!["a", "b"]));
+        // Same tools, other arguments
+        assert_eq!(call_log(&[("a", "1"), ("b", "2"), ("a", "1"), ("b", "3")]).cycle(), None);
+    }
+
+    #[test]
+    fn calls_that_go_on_are_no_cycle() {
+        let calls: Vec<String> = (0..12).map(|i| i.to_string()).collect();
+        let calls: Vec<(&str, &str)> = calls.iter().map(|x| ("a", x.as_str())).collect();
+        assert_eq!(call_log(&calls).cycle(), None);
+        assert_eq!(CallLog::new(None).cycle(), None);
+    }
+
+    fn string_value(s: &str) -> Value {
+        Value {
+            kind: Some(Kind::StringValue(s.to_string())),
+        }
+    }
+
+    fn read_call(path: &Path) -> Part {
+        Part::new(Data::FunctionCall(FunctionCall {
+            id: String::new(),
+            name: "read_fs".to_string(),
+            args: Some(Struct {
+                fields: BTreeMap::from([("path".to_string(), string_value(&path.to_string_lossy()))]),
+            }),
+        }))
+    }
+
+    #[tokio::test]
+    async fn responses_keep_the_ids_of_their_calls() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let dir = temp_dir("chat-two-calls");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        fs::write(dir.join("b.txt"), "b").unwrap();
+
+        let mut content = Content::model(vec![read_call(&dir.join("a.txt")), read_call(&dir.join("b.txt"))]);
+        assign_call_ids(&mut content, 3);
+        let calls: Vec<FunctionCall> = content
+            .parts
+            .iter()
+            .filter_map(|part| match &part.data {
+                Some(Data::FunctionCall(call)) => Some(call.clone()),
+                _ => None,
+            })
+            .collect();
+        assert_ne!(calls[0].id, calls[1].id);
+
+        let (sender, _receiver) = channel(16);
+        let sender = FrameSender::new(sender, Framing::Sse);
+        let commands = CommandTools::current();
+        let cache = ToolCache::default();
+        let arrived = std::sync::Mutex::new(vec![]);
+
+        // The first call's response comes in last
+        let run = |call: FunctionCall, delay: u64| {
+            let (sender, commands, cache, arrived) = (&sender, &commands, &cache, &arrived);
+            async move {
+                sleep(Duration::from_millis(delay)).await;
+                let resp = handle_function_call(sender, call, commands, cache).await;
+                arrived.lock().unwrap().push(resp.clone());
+                resp
+            }
+        };
+        let resps = join_all([run(calls[0].clone(), 100), run(calls[1].clone(), 0)]).await;
+        let arrived = arrived.into_inner().unwrap();
+        assert_eq!(arrived[0].id, calls[1].id);
+
+        for (call, resp) in calls.iter().zip(&resps) {
+            assert_eq!(resp.id, call.id);
+            let fields = &resp.response.as_ref().unwrap().fields;
+            assert!(matches!(&fields[tools::CALL_ID_FIELD].kind, Some(Kind::StringValue(id)) if *id == call.id));
+            assert!(!fields.contains_key("error"));
+        }
+
+        // Responses are matched to calls by id, in whatever order they're given
+        let arrived = Content::tool(arrived.into_iter().map(|resp| Part::new(Data::FunctionResponse(resp))).collect());
+        assert!(validate_history(&[content, arrived]).is_ok());
+    }
+
+    #[test]
+    fn truncated_nested_result_stays_a_struct_under_the_limit() {
+        let lines = (0..500).map(|i| string_value(&format!("line {}", i))).collect();
+        let inner = Struct {
+            fields: BTreeMap::from([
+                ("text".to_string(), string_value(&"x".repeat(10_000))),
+                ("lines".to_string(), Value { kind: Some(Kind::ListValue(ListValue { values: lines })) }),
+                ("path".to_string(), string_value("a.txt")),
+            ]),
+        };
+        let result = Struct {
+            fields: BTreeMap::from([
+                ("file".to_string(), Value { kind: Some(Kind::StructValue(inner)) }),
+                (tools::CALL_ID_FIELD.to_string(), string_value("call_0_0")),
+            ]),
+        };
+        let resp = FunctionResponse {
+            id: "call_0_0".to_string(),
+            name: "read_fs".to_string(),
+            response: Some(result),
+        };
+        let mut content = Content::tool(vec![Part::new(Data::FunctionResponse(resp))]);
+
+        let max = 2_000;
+        truncate_tool_results(&mut content, max);
+
+        let Some(Data::FunctionResponse(resp)) = &content.parts[0].data else {
+            panic!("not a function response");
+        };
+        let fields = &resp.response.as_ref().unwrap().fields;
+        assert!(json_len(resp.response.as_ref().unwrap()) <= max);
+        assert!(matches!(fields["truncated"].kind, Some(Kind::BoolValue(true))));
+        assert!(matches!(&fields[tools::CALL_ID_FIELD].kind, Some(Kind::StringValue(id)) if id == "call_0_0"));
+
+        let Some(Kind::StructValue(inner)) = &fields["file"].kind else {
+            panic!("nested struct is gone");
+        };
+        assert!(matches!(&inner.fields["path"].kind, Some(Kind::StringValue(path)) if path == "a.txt"));
+        assert!(matches!(&inner.fields["text"].kind, Some(Kind::StringValue(text)) if text.ends_with("chars omitted]")));
+        assert!(matches!(&inner.fields["lines"].kind, Some(Kind::ListValue(_))));
+
+        // Still valid JSON that reads back as an object
+        let json = serde_json::to_string(resp.response.as_ref().unwrap()).unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(&json).unwrap()["file"].is_object());
+    }
+
+    /// Stands in for the model, giving the next scripted reply to each request, which it keeps
+    struct Script {
+        replies: std::sync::Mutex<VecDeque<VecDeque<GenerateContentResponse>>>,
+        requests: std::sync::Mutex<Vec<Vec<google_ai_rs::Content>>>,
+    }
+
+    impl Script {
+        fn new(replies: Vec<Vec<GenerateContentResponse>>) -> Self {
+            Self {
+                replies: std::sync::Mutex::new(replies.into_iter().map(VecDeque::from).collect()),
+                requests: std::sync::Mutex::default(),
+            }
+        }
+    }
+
+    impl Model for Script {
+        type Stream = VecDeque<GenerateContentResponse>;
+
+        async fn stream(&self, contents: Vec<google_ai_rs::Content>) -> Result<Self::Stream, google_ai_rs::Error> {
+            self.requests.lock().unwrap().push(contents);
+            Ok(self.replies.lock().unwrap().pop_front().unwrap_or_default())
+        }
+    }
+
+    impl Responses for VecDeque<GenerateContentResponse> {
+        async fn next(&mut self) -> Result<Option<GenerateContentResponse>, google_ai_rs::Error> {
+            Ok(self.pop_front())
+        }
+    }
+
+    fn reply(text: &str) -> GenerateContentResponse {
+        reply_with(vec![Part::new(Data::from(text.to_string()))])
+    }
+
+    fn reply_with(parts: Vec<Part>) -> GenerateContentResponse {
+        GenerateContentResponse {
+            candidates: vec![google_ai_rs::proto::Candidate {
+                index: Some(0),
+                content: Some(Content::model(parts).into()),
+                finish_reason: FinishReason::Stop as i32,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    /// Held by tests using the one history there is, or running tools, which one turns approvals on for
+    static HISTORY_TEST: Mutex<()> = Mutex::const_new(());
+
+    #[tokio::test]
+    async fn chat_stream_does_not_echo_the_posted_message() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let model = Script::new(vec![vec![reply("It's empty")]]);
+        let chat = Content {
+            parts: vec![Part::new(Data::from("What's in the box?".to_string()))],
+            role: Role::User,
+        };
+
+        let (sender, mut receiver) = channel(16);
+        process_chat(Generation::wait().await, chat, FrameSender::new(sender, Framing::Sse), &model).await;
+
+        let mut frames = vec![];
+        while let Some(frame) = receiver.recv().await {
+            frames.push(frame_text(frame.unwrap()));
+        }
+        assert_eq!(frames.len(), 1, "{:?}", frames);
+        assert!(frames[0].starts_with("data: ") && frames[0].contains("It's empty"));
+        assert!(frames.iter().all(|frame| !frame.contains("What's in the box?")));
+    }
+
+    #[tokio::test]
+    async fn tool_responses_go_back_to_the_model_as_function() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let dir = temp_dir("chat-function-role");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let model = Script::new(vec![vec![reply_with(vec![read_call(&dir.join("a.txt"))])], vec![reply("Done")]]);
+        let chat = Content {
+            parts: vec![Part::new(Data::from("Read a.txt".to_string()))],
+            role: Role::User,
+        };
+
+        let (sender, _receiver) = channel(16);
+        process_chat(Generation::wait().await, chat, FrameSender::new(sender, Framing::Sse), &model).await;
+
+        let requests = model.requests.into_inner().unwrap();
+        assert_eq!(requests.len(), 2);
+        let [.., call, response] = requests[1].as_slice() else {
+            panic!("no tool round trip in {} contents", requests[1].len());
+        };
+        assert_eq!(call.role, "model");
+        assert_eq!(response.role, "function");
+
+        let call: Content = call.clone().into();
+        let Some(Data::FunctionCall(call)) = &call.parts[0].data else {
+            panic!("not a function call");
+        };
+        let response = response.parts[0].clone().into();
+        let Part { data: Some(Data::FunctionResponse(response)), .. } = response else {
+            panic!("not a function response");
+        };
+        assert_eq!(response.id, call.id);
+        assert_eq!(response.name, "read_fs");
+    }
+
+    #[tokio::test]
+    async fn denied_call_is_never_started() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let dir = temp_dir("chat-denied");
+        fs::write(dir.join("a.txt"), "a").unwrap();
+        let model = Script::new(vec![vec![reply_with(vec![read_call(&dir.join("a.txt"))])], vec![reply("Fine")]]);
+        let chat = Content {
+            parts: vec![Part::new(Data::from("Read a.txt".to_string()))],
+            role: Role::User,
+        };
+
+        let approving = |name: &str| (name == "YAS_APPROVE_TOOLS").then(|| "1".to_string());
+        CONFIG.replace(crate::config::Config::from_vars(&approving).unwrap());
+        let (sender, mut receiver) = channel(16);
+        let sender = FrameSender::new(sender, Framing::Sse);
+        let generation = Generation::wait().await;
+        let denying = async {
+            let mut frames = vec![];
+            while let Some(frame) = receiver.recv().await {
+                let frame = frame_text(frame.unwrap());
+                if let Some(data) = frame.strip_prefix("event: approval_request\ndata: ") {
+                    let request: serde_json::Value = serde_json::from_str(data).unwrap();
+                    assert!(approve(request["id"].as_str().unwrap(), false));
+                }
+                frames.push(frame);
+            }
+            frames
+        };
+        let ((), frames) = tokio::join!(
+            process_chat(generation, chat, sender, &model),
+            denying
+        );
+        CONFIG.replace(crate::config::Config::from_vars(&|_| None).unwrap());
+
+        assert!(frames.iter().any(|frame| frame.starts_with("event: approval_request")));
+        assert!(frames.iter().any(|frame| frame.contains("Denied by user")));
+        assert!(frames.iter().all(|frame| !frame.starts_with("event: tool_started")), "{:?}", frames);
+    }
+
+    #[tokio::test]
+    async fn display_goes_to_the_client_alone() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let dir = temp_dir("chat-display");
+        fs::write(dir.join("a.rs"), "fn main() {}\n").unwrap();
+        let Part { data: Some(Data::FunctionCall(mut call)), .. } = read_call(&dir.join("a.rs")) else {
+            unreachable!();
+        };
+        call.args.as_mut().unwrap().fields.insert("highlight".to_string(), Value { kind: Some(Kind::BoolValue(true)) });
+        let model = Script::new(vec![vec![reply_with(vec![Part::new(Data::FunctionCall(call))])], vec![reply("Done")]]);
+
+        let (sender, mut receiver) = channel(16);
+        process_chat(Generation::wait().await, text(Role::User, "Show a.rs"), FrameSender::new(sender, Framing::Sse), &model).await;
+
+        let mut frames = vec![];
+        while let Some(frame) = receiver.recv().await {
+            frames.push(frame_text(frame.unwrap()));
+        }
+        let tool_frame = frames.iter().find(|frame| frame.contains("\"function_response\"")).unwrap_or_else(|| panic!("{:?}", frames));
+        assert!(tool_frame.contains(&format!("\"{}\"", tools::DISPLAY_FIELD)), "{}", tool_frame);
+
+        let requests = model.requests.into_inner().unwrap();
+        let response: Part = requests[1].last().unwrap().parts[0].clone().into();
+        let Some(Data::FunctionResponse(FunctionResponse { response: Some(response), .. })) = response.data else {
+            panic!("not a function response");
+        };
+        assert!(response.fields.contains_key("result"));
+        assert!(!response.fields.contains_key(tools::DISPLAY_FIELD));
+        let history = HISTORY.lock().await;
+        assert!(!serde_json::to_string(&*history).unwrap().contains(&format!("\"{}\"", tools::DISPLAY_FIELD)));
+    }
+
+    fn text(role: Role, text: &str) -> Content {
+        Content {
+            parts: vec![Part::new(Data::from(text.to_string()))],
+            role,
+        }
+    }
+
+    #[tokio::test]
+    async fn model_is_told_once_tool_calls_are_used_up() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let capped = |name: &str| (name == "YAS_SESSION_MAX_TOOL_CALLS").then(|| "1".to_string());
+        CONFIG.replace(crate::config::Config::from_vars(&capped).unwrap());
+        SESSION_TOOL_CALLS.store(1, Ordering::Relaxed);
+
+        let model = Script::new(vec![vec![reply("Without tools, then")]]);
+        let (sender, _receiver) = channel(16);
+        process_chat(Generation::wait().await, text(Role::User, "Hi"), FrameSender::new(sender, Framing::Sse), &model).await;
+        let notice = model.requests.into_inner().unwrap()[0].last().unwrap().clone();
+
+        assert!(import_chat(vec![text(Role::User, "Hi")], false).await.is_ok());
+        let after_import = tool_cap_content();
+        CONFIG.replace(crate::config::Config::from_vars(&|_| None).unwrap());
+
+        assert_eq!(notice.role, "user");
+        let notice: Content = notice.into();
+        assert!(matches!(&notice.parts[0].data, Some(Data::Text { text }) if text.contains("used up the 1 tool calls")));
+        assert!(after_import.is_none());
+        assert!(!HISTORY.lock().await.iter().any(|content| content.parts.iter().any(
+            |part| matches!(&part.data, Some(Data::Text { text }) if text.contains("[System notice]"))
+        )));
+    }
+
+    fn texts(history: &[Content]) -> Vec<String> {
+        history
+            .iter()
+            .map(|content| match &content.parts[0].data {
+                Some(Data::Text { text }) => text.clone(),
+                Some(Data::FunctionCall(_)) => "call".to_string(),
+                Some(Data::FunctionResponse(_)) => "response".to_string(),
+                _ => "other".to_string(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn deleting_a_user_entry_takes_the_tool_calls_after_it() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let call = Content::model(vec![read_call(Path::new("a.txt"))]);
+        let response = Content::tool(vec![Part::new(Data::FunctionResponse(response("", "a")))]);
+        *HISTORY.lock().await = vec![
+            text(Role::User, "Hi"),
+            text(Role::Model, "Hello"),
+            text(Role::User, "Read a.txt"),
+            call.clone(),
+            response.clone(),
+            call,
+            response,
+            text(Role::Model, "Done"),
+        ];
+
+        assert!(delete_message(2).await);
+        assert_eq!(texts(&HISTORY.lock().await), ["Hi", "Hello", "Done"]);
+        assert!(delete_message(0).await);
+        assert_eq!(texts(&HISTORY.lock().await), ["Hello", "Done"]);
+    }
+
+    #[tokio::test]
+    async fn deleting_past_the_end_changes_nothing() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        *HISTORY.lock().await = vec![text(Role::User, "Hi")];
+
+        assert!(!delete_message(1).await);
+        assert!(!delete_message(usize::MAX).await);
+        assert_eq!(texts(&HISTORY.lock().await), ["Hi"]);
+    }
+
+    #[tokio::test]
+    async fn reading_history_does_not_wait_for_a_stalled_client() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        let model = Script::new(vec![vec![reply("First"), reply("Second")]]);
+        let chat = Content {
+            parts: vec![Part::new(Data::from("Hi".to_string()))],
+            role: Role::User,
+        };
+
+        // Room for one frame, and nothing ever reads it
+        let (sender, receiver) = channel(1);
+        let generation = Generation::wait().await;
+        let generating = tokio::spawn(async move {
+            process_chat(generation, chat, FrameSender::new(sender, Framing::Sse), &model).await;
+        });
+
+        // The first reply fills the channel, then the second waits for room in it
+        while receiver.is_empty() {
+            sleep(Duration::from_millis(10)).await;
+        }
+        sleep(Duration::from_millis(50)).await;
+        assert!(!generating.is_finished());
+        assert!(Generation::try_begin().is_none());
+
+        let history = timeout(Duration::from_secs(1), get_chat()).await;
+        assert!(history.is_ok(), "GET /chat waited on the stalled generation");
+
+        // The client going away ends the generation
+        drop(receiver);
+        timeout(Duration::from_secs(5), generating).await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn cleared_history_stays_empty_on_reload() {
+        init_config();
+        let _history = HISTORY_TEST.lock().await;
+        HISTORY.lock().await.push(Content::model(vec![Part::new(Data::from("Hello".to_string()))]));
+        save_history().await;
+        assert!(!load_history().is_empty());
+
+        clear_chat().await;
+        assert!(get_chat().await.is_empty());
+        assert!(load_history().is_empty());
+        assert!(load_history_or_seed().is_empty());
+    }
+
+    #[test]
+    fn sse_multiline_data_gets_a_line_each() {
+        assert_eq!(sse_frame(None, "a\nb\r\nc"), "data: a\ndata: b\ndata: c\n\n");
+        assert_eq!(sse_frame(Some("x"), "a\n"), "event: x\ndata: a\ndata: \n\n");
+    }
+}
@@ -0,0 +1,292 @@
+use std::env::var;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Most candidates the API generates for one request
+const MAX_CANDIDATE_COUNT: i32 = 8;
+
+/// Looks up a variable by name
+type Vars<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+fn env_flag(vars: Vars, name: &str) -> bool {
+    matches!(
+        vars(name).as_deref(),
+        Some("1") | Some("true") | Some("yes") | Some("on")
+    )
+}
+
+fn invalid(name: &str, value: &str) -> String {
+    format!("variable {} has invalid value '{}'", name, value)
+}
+
+fn env_parse<T: FromStr>(vars: Vars, name: &str, default: T) -> Result<T, String> {
+    match vars(name) {
+        Some(v) => v.parse().map_err(|_| invalid(name, &v)),
+        None => Ok(default),
+    }
+}
+
+fn env_parse_opt<T: FromStr>(vars: Vars, name: &str) -> Result<Option<T>, String> {
+    vars(name)
+        .filter(|v| !v.is_empty())
+        .map(|v| v.parse().map_err(|_| invalid(name, &v)))
+        .transpose()
+}
+
+fn env_list(vars: Vars, name: &str) -> Vec<String> {
+    vars(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+pub struct Config {
+    /// Enables tools that modify the filesystem (`YAS_ALLOW_DESTRUCTIVE`)
+    pub allow_destructive: bool,
+    /// Disables every tool, leaving a plain chat server (`YAS_NO_TOOLS`)
+    pub no_tools: bool,
+    /// Time a single tool call may run before it's abandoned (`YAS_TOOL_TIMEOUT_SECS`)
+    pub tool_timeout: Duration,
+    /// Model message shown as the first entry of a new conversation (`YAS_SEED_MESSAGE`)
+    pub seed_message: Option<String>,
+    /// Prefixes of environment variables `get_env` may read (`YAS_ENV_ALLOW`, comma-separated)
+    pub env_allow: Vec<String>,
+    /// Logs expected, noisy events such as client disconnects (`YAS_DEBUG`)
+    pub debug: bool,
+    /// Serves tools over MCP on stdio instead of running the chat server (`YAS_MCP`)
+    pub mcp: bool,
+    /// Bearer token for `/admin/*` endpoints, which are disabled when unset (`YAS_ADMIN_TOKEN`)
+    pub admin_token: Option<String>,
+    /// JSON file declaring extra tools that run a fixed command (`YAS_TOOLS_FILE`)
+    pub tools_file: Option<PathBuf>,
+    /// Length past which a tool result is cut down once the model has seen it in full
+    /// (`YAS_MAX_TOOL_RESULT_CHARS`)
+    pub max_tool_result_chars: Option<usize>,
+    /// Time a connection may take to send request headers, idle keep-alive time included;
+    /// `None` when `YAS_CONN_TIMEOUT` is `0`
+    pub conn_timeout: Option<Duration>,
+    /// Longest a `/chat` event stream stays open, however busy (`YAS_MAX_STREAM_SECS`)
+    pub max_stream: Option<Duration>,
+    /// Largest inline blob a posted message may carry, decoded (`YAS_MAX_BLOB_BYTES`)
+    pub max_blob_bytes: usize,
+    /// Largest total of inline blobs in a posted message, decoded (`YAS_MAX_ATTACHMENT_BYTES`)
+    pub max_attachment_bytes: usize,
+    /// Models besides the default a request may choose with `?model=` (`YAS_ALLOWED_MODELS`,
+    /// comma-separated)
+    pub allowed_models: Vec<String>,
+    /// Path prefix every route is served under, without a trailing slash; empty when served at
+    /// the root (`YAS_BASE_PATH`)
+    pub base_path: String,
+    /// Directory that tools writing files must stay within, canonicalized (`YAS_WRITE_ROOT`)
+    pub write_root: Option<PathBuf>,
+    /// Lets `read_fs` read through symbolic links, which may lead anywhere; the model can't
+    /// turn this on itself (`YAS_FOLLOW_SYMLINKS`)
+    pub follow_symlinks: bool,
+    /// Treats finish reasons this build doesn't know as failures rather than carrying on
+    /// (`YAS_STRICT_FINISH_REASON`)
+    pub strict_finish_reason: bool,
+    /// Enables `fetch_url`, which gives the model outbound network access (`YAS_ENABLE_FETCH_URL`)
+    pub enable_fetch_url: bool,
+    /// Lets `fetch_url` reach loopback, private and link-local addresses (`YAS_ALLOW_PRIVATE_FETCH`)
+    pub allow_private_fetch: bool,
+    /// Holds every tool call until the user approves it through `POST /chat/approve`
+    /// (`YAS_APPROVE_TOOLS`)
+    pub approve_tools: bool,
+    /// Time a tool call waits for approval before it's denied (`YAS_APPROVAL_TIMEOUT_SECS`)
+    pub approval_timeout: Duration,
+    /// Returns file contents inside a fenced block labeled as untrusted data (`YAS_WRAP_FILE_CONTENT`)
+    pub wrap_file_content: bool,
+    /// Reconnect delay suggested to SSE clients at the start of a `/chat` stream (`YAS_SSE_RETRY_MS`)
+    pub sse_retry: Option<Duration>,
+    /// Paths a glob may walk through before the search is stopped; `None` for no limit
+    /// (`YAS_MAX_WALK_ENTRIES`)
+    pub max_walk_entries: Option<usize>,
+    /// Files whose content goes before history in every request, read anew each turn
+    /// (`YAS_CONTEXT_FILES`)
+    pub context_files: Vec<PathBuf>,
+    /// Lists the enabled tools in the system instruction (`YAS_TOOL_BANNER`)
+    pub tool_banner: bool,
+    /// Candidates the model generates per response; those past the first can replace it through
+    /// `POST /chat/choose` (`YAS_CANDIDATE_COUNT`)
+    pub candidate_count: i32,
+    /// Unix socket to listen on instead of TCP port 8080 (`YAS_UNIX_SOCKET`)
+    pub unix_socket: Option<PathBuf>,
+    /// Most parts a posted message may have (`YAS_MAX_PARTS`)
+    pub max_parts: usize,
+    /// Largest `POST /chat` body, read no further than this (`YAS_MAX_BODY_BYTES`)
+    pub max_body_bytes: usize,
+    /// Most `POST /chat` streams running at once; `None` for no limit (`YAS_MAX_ACTIVE_STREAMS`)
+    pub max_active_streams: Option<usize>,
+    /// Answers `POST /chat` with `409` while a generation runs, instead of queueing it behind
+    /// (`YAS_REJECT_BUSY`)
+    pub reject_busy: bool,
+    /// Most tool calls run in one conversation, until it's cleared or replaced; `None` for no limit
+    /// (`YAS_SESSION_MAX_TOOL_CALLS`)
+    pub session_max_tool_calls: Option<usize>,
+    /// Tool results longer than this as JSON are cut down in the frames streamed to the client,
+    /// which can fetch them whole through `GET /chat/tool-result/{id}` (`YAS_TOOL_PREVIEW_CHARS`)
+    pub tool_preview_chars: Option<usize>,
+    /// Enables `list_processes`, which reads `/proc` (`YAS_ENABLE_PROC_TOOLS`)
+    pub enable_proc_tools: bool,
+    /// Tool calls of a generation looked back over for a cycle the model keeps repeating,
+    /// which ends the generation; `None` to never look (`YAS_LOOP_WINDOW`)
+    pub loop_window: Option<usize>,
+    /// Longest a turn waits in total, retrying, while the API is throttling
+    /// (`YAS_THROTTLE_MAX_WAIT_SECS`)
+    pub throttle_max_wait: Duration,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("allow_destructive", &self.allow_destructive)
+            .field("no_tools", &self.no_tools)
+            .field("tool_timeout", &self.tool_timeout)
+            .field("seed_message", &self.seed_message)
+            .field("env_allow", &self.env_allow)
+            .field("debug", &self.debug)
+            .field("mcp", &self.mcp)
+            .field("admin_token", &self.admin_token.as_ref().map(|_| "<redacted>"))
+            .field("tools_file", &self.tools_file)
+            .field("max_tool_result_chars", &self.max_tool_result_chars)
+            .field("conn_timeout", &self.conn_timeout)
+            .field("max_stream", &self.max_stream)
+            .field("max_blob_bytes", &self.max_blob_bytes)
+            .field("max_attachment_bytes", &self.max_attachment_bytes)
+            .field("allowed_models", &self.allowed_models)
+            .field("base_path", &self.base_path)
+            .field("write_root", &self.write_root)
+            .field("follow_symlinks", &self.follow_symlinks)
+            .field("strict_finish_reason", &self.strict_finish_reason)
+            .field("enable_fetch_url", &self.enable_fetch_url)
+            .field("allow_private_fetch", &self.allow_private_fetch)
+            .field("approve_tools", &self.approve_tools)
+            .field("approval_timeout", &self.approval_timeout)
+            .field("wrap_file_content", &self.wrap_file_content)
+            .field("sse_retry", &self.sse_retry)
+            .field("max_walk_entries", &self.max_walk_entries)
+            .field("context_files", &self.context_files)
+            .field("tool_banner", &self.tool_banner)
+            .field("candidate_count", &self.candidate_count)
+            .field("unix_socket", &self.unix_socket)
+            .field("max_parts", &self.max_parts)
+            .field("max_body_bytes", &self.max_body_bytes)
+            .field("max_active_streams", &self.max_active_streams)
+            .field("reject_busy", &self.reject_busy)
+            .field("session_max_tool_calls", &self.session_max_tool_calls)
+            .field("tool_preview_chars", &self.tool_preview_chars)
+            .field("enable_proc_tools", &self.enable_proc_tools)
+            .field("loop_window", &self.loop_window)
+            .field("throttle_max_wait", &self.throttle_max_wait)
+            .finish()
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(&|name| var(name).ok())
+    }
+
+    /// Reads the configuration from variables looked up through `vars`, naming the first invalid one
+    pub fn from_vars(vars: Vars) -> Result<Self, String> {
+        Ok(Self {
+            allow_destructive: env_flag(vars, "YAS_ALLOW_DESTRUCTIVE"),
+            no_tools: env_flag(vars, "YAS_NO_TOOLS"),
+            tool_timeout: Duration::from_secs(env_parse(vars, "YAS_TOOL_TIMEOUT_SECS", 30)?),
+            seed_message: vars("YAS_SEED_MESSAGE").filter(|v| !v.is_empty()),
+            env_allow: env_list(vars, "YAS_ENV_ALLOW"),
+            debug: env_flag(vars, "YAS_DEBUG"),
+            mcp: env_flag(vars, "YAS_MCP"),
+            admin_token: vars("YAS_ADMIN_TOKEN").filter(|v| !v.is_empty()),
+            tools_file: vars("YAS_TOOLS_FILE").filter(|v| !v.is_empty()).map(PathBuf::from),
+            max_tool_result_chars: env_parse_opt(vars, "YAS_MAX_TOOL_RESULT_CHARS")?,
+            conn_timeout: match env_parse(vars, "YAS_CONN_TIMEOUT", 30)? {
+                0 => None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            max_stream: env_parse_opt(vars, "YAS_MAX_STREAM_SECS")?.map(Duration::from_secs),
+            max_blob_bytes: env_parse(vars, "YAS_MAX_BLOB_BYTES", 8 * 1024 * 1024)?,
+            max_attachment_bytes: env_parse(vars, "YAS_MAX_ATTACHMENT_BYTES", 16 * 1024 * 1024)?,
+            allowed_models: env_list(vars, "YAS_ALLOWED_MODELS"),
+            base_path: match vars("YAS_BASE_PATH").unwrap_or_default().trim_matches('/') {
+                "" => String::new(),
+                v => format!("/{}", v),
+            },
+            write_root: vars("YAS_WRITE_ROOT")
+                .filter(|v| !v.is_empty())
+                .map(|v| {
+                    PathBuf::from(&v)
+                        .canonicalize()
+                        .map_err(|e| format!("{}: {}", invalid("YAS_WRITE_ROOT", &v), e))
+                })
+                .transpose()?,
+            follow_symlinks: env_flag(vars, "YAS_FOLLOW_SYMLINKS"),
+            strict_finish_reason: env_flag(vars, "YAS_STRICT_FINISH_REASON"),
+            enable_fetch_url: env_flag(vars, "YAS_ENABLE_FETCH_URL"),
+            allow_private_fetch: env_flag(vars, "YAS_ALLOW_PRIVATE_FETCH"),
+            approve_tools: env_flag(vars, "YAS_APPROVE_TOOLS"),
+            approval_timeout: Duration::from_secs(env_parse(vars, "YAS_APPROVAL_TIMEOUT_SECS", 300)?),
+            wrap_file_content: env_flag(vars, "YAS_WRAP_FILE_CONTENT"),
+            sse_retry: env_parse_opt(vars, "YAS_SSE_RETRY_MS")?.map(Duration::from_millis),
+            max_walk_entries: match env_parse(vars, "YAS_MAX_WALK_ENTRIES", 1_000_000)? {
+                0 => None,
+                max => Some(max),
+            },
+            context_files: env_list(vars, "YAS_CONTEXT_FILES").into_iter().map(PathBuf::from).collect(),
+            tool_banner: env_flag(vars, "YAS_TOOL_BANNER"),
+            candidate_count: match env_parse(vars, "YAS_CANDIDATE_COUNT", 1)? {
+                count @ 1..=MAX_CANDIDATE_COUNT => count,
+                count => {
+                    return Err(format!(
+                        "{}; expected 1 to {}",
+                        invalid("YAS_CANDIDATE_COUNT", &count.to_string()),
+                        MAX_CANDIDATE_COUNT
+                    ));
+                }
+            },
+            unix_socket: vars("YAS_UNIX_SOCKET").filter(|v| !v.is_empty()).map(PathBuf::from),
+            max_parts: env_parse(vars, "YAS_MAX_PARTS", 256)?,
+            max_body_bytes: env_parse(vars, "YAS_MAX_BODY_BYTES", 32 * 1024 * 1024)?,
+            max_active_streams: env_parse_opt(vars, "YAS_MAX_ACTIVE_STREAMS")?,
+            reject_busy: env_flag(vars, "YAS_REJECT_BUSY"),
+            session_max_tool_calls: env_parse_opt(vars, "YAS_SESSION_MAX_TOOL_CALLS")?,
+            tool_preview_chars: env_parse_opt(vars, "YAS_TOOL_PREVIEW_CHARS")?,
+            enable_proc_tools: env_flag(vars, "YAS_ENABLE_PROC_TOOLS"),
+            loop_window: match env_parse(vars, "YAS_LOOP_WINDOW", 12)? {
+                0 => None,
+                window => Some(window),
+            },
+            throttle_max_wait: Duration::from_secs(env_parse(vars, "YAS_THROTTLE_MAX_WAIT_SECS", 60)?),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_values_are_errors_naming_the_variable() {
+        let e = Config::from_vars(&|name| (name == "YAS_MAX_PARTS").then(|| "many".to_string())).unwrap_err();
+        assert_eq!(e, "variable YAS_MAX_PARTS has invalid value 'many'");
+        assert!(Config::from_vars(&|_| None).is_ok());
+    }
+
+    #[test]
+    fn candidate_count_is_one_to_eight() {
+        let count = |value: &'static str| {
+            Config::from_vars(&move |name| (name == "YAS_CANDIDATE_COUNT").then(|| value.to_string()))
+                .map(|config| config.candidate_count)
+        };
+        assert_eq!(count("1"), Ok(1));
+        assert_eq!(count("8"), Ok(8));
+        assert!(count("0").is_err());
+        assert!(count("9").is_err());
+        assert!(count("-1").is_err());
+    }
+}
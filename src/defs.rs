@@ -1,5 +1,5 @@
 use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Struct {
@@ -19,10 +19,10 @@ impl From<prost_types::Struct> for Struct {
     }
 }
 
-impl Into<prost_types::Struct> for Struct {
-    fn into(self) -> prost_types::Struct {
+impl From<Struct> for prost_types::Struct {
+    fn from(value: Struct) -> Self {
         prost_types::Struct {
-            fields: self
+            fields: value
                 .fields
                 .into_iter()
                 .map(|(k, v)| (k, v.into()))
@@ -45,16 +45,17 @@ impl From<prost_types::ListValue> for ListValue {
     }
 }
 
-impl Into<prost_types::ListValue> for ListValue {
-    fn into(self) -> prost_types::ListValue {
+impl From<ListValue> for prost_types::ListValue {
+    fn from(value: ListValue) -> Self {
         prost_types::ListValue {
-            values: self.values.into_iter().map(|v| v.into()).collect(),
+            values: value.values.into_iter().map(|v| v.into()).collect(),
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(untagged)]
+#[allow(clippy::enum_variant_names)]
 pub enum Kind {
     NullValue(i32),
     NumberValue(f64),
@@ -77,9 +78,9 @@ impl From<prost_types::value::Kind> for Kind {
     }
 }
 
-impl Into<prost_types::value::Kind> for Kind {
-    fn into(self) -> prost_types::value::Kind {
-        match self {
+impl From<Kind> for prost_types::value::Kind {
+    fn from(value: Kind) -> Self {
+        match value {
             Kind::NullValue(v) => prost_types::value::Kind::NullValue(v),
             Kind::NumberValue(v) => prost_types::value::Kind::NumberValue(v),
             Kind::StringValue(v) => prost_types::value::Kind::StringValue(v),
@@ -104,10 +105,10 @@ impl From<prost_types::Value> for Value {
     }
 }
 
-impl Into<prost_types::Value> for Value {
-    fn into(self) -> prost_types::Value {
+impl From<Value> for prost_types::Value {
+    fn from(value: Value) -> Self {
         prost_types::Value {
-            kind: self.kind.map(|v| v.into()),
+            kind: value.kind.map(|v| v.into()),
         }
     }
 }
@@ -129,12 +130,12 @@ impl From<google_ai_rs::FunctionCall> for FunctionCall {
     }
 }
 
-impl Into<google_ai_rs::FunctionCall> for FunctionCall {
-    fn into(self) -> google_ai_rs::FunctionCall {
+impl From<FunctionCall> for google_ai_rs::FunctionCall {
+    fn from(value: FunctionCall) -> Self {
         google_ai_rs::FunctionCall {
-            id: self.id,
-            name: self.name,
-            args: self.args.map(|v| v.into()),
+            id: value.id,
+            name: value.name,
+            args: value.args.map(|v| v.into()),
         }
     }
 }
@@ -156,12 +157,12 @@ impl From<google_ai_rs::proto::FunctionResponse> for FunctionResponse {
     }
 }
 
-impl Into<google_ai_rs::proto::FunctionResponse> for FunctionResponse {
-    fn into(self) -> google_ai_rs::proto::FunctionResponse {
+impl From<FunctionResponse> for google_ai_rs::proto::FunctionResponse {
+    fn from(value: FunctionResponse) -> Self {
         google_ai_rs::proto::FunctionResponse {
-            id: self.id,
-            name: self.name,
-            response: self.response.map(|v| v.into()),
+            id: value.id,
+            name: value.name,
+            response: value.response.map(|v| v.into()),
         }
     }
 }
@@ -181,11 +182,11 @@ impl From<google_ai_rs::proto::Blob> for Blob {
     }
 }
 
-impl Into<google_ai_rs::proto::Blob> for Blob {
-    fn into(self) -> google_ai_rs::proto::Blob {
+impl From<Blob> for google_ai_rs::proto::Blob {
+    fn from(value: Blob) -> Self {
         google_ai_rs::proto::Blob {
-            mime_type: self.mime_type,
-            data: self.data,
+            mime_type: value.mime_type,
+            data: value.data,
         }
     }
 }
@@ -205,11 +206,11 @@ impl From<google_ai_rs::proto::FileData> for FileData {
     }
 }
 
-impl Into<google_ai_rs::proto::FileData> for FileData {
-    fn into(self) -> google_ai_rs::proto::FileData {
+impl From<FileData> for google_ai_rs::proto::FileData {
+    fn from(value: FileData) -> Self {
         google_ai_rs::proto::FileData {
-            mime_type: self.mime_type,
-            file_uri: self.file_uri,
+            mime_type: value.mime_type,
+            file_uri: value.file_uri,
         }
     }
 }
@@ -229,11 +230,11 @@ impl From<google_ai_rs::proto::ExecutableCode> for ExecutableCode {
     }
 }
 
-impl Into<google_ai_rs::proto::ExecutableCode> for ExecutableCode {
-    fn into(self) -> google_ai_rs::proto::ExecutableCode {
+impl From<ExecutableCode> for google_ai_rs::proto::ExecutableCode {
+    fn from(value: ExecutableCode) -> Self {
         google_ai_rs::proto::ExecutableCode {
-            language: self.language,
-            code: self.code,
+            language: value.language,
+            code: value.code,
         }
     }
 }
@@ -253,17 +254,18 @@ impl From<google_ai_rs::proto::CodeExecutionResult> for CodeExecutionResult {
     }
 }
 
-impl Into<google_ai_rs::proto::CodeExecutionResult> for CodeExecutionResult {
-    fn into(self) -> google_ai_rs::proto::CodeExecutionResult {
+impl From<CodeExecutionResult> for google_ai_rs::proto::CodeExecutionResult {
+    fn from(value: CodeExecutionResult) -> Self {
         google_ai_rs::proto::CodeExecutionResult {
-            outcome: self.outcome,
-            output: self.output,
+            outcome: value.outcome,
+            output: value.output,
         }
     }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 #[serde(tag = "type", rename_all = "snake_case")]
+#[allow(clippy::enum_variant_names)]
 pub enum Data {
     Text{ text: String },
     InlineData(Blob),
@@ -294,9 +296,9 @@ impl From<google_ai_rs::Data> for Data {
     }
 }
 
-impl Into<google_ai_rs::Data> for Data {
-    fn into(self) -> google_ai_rs::Data {
-        match self {
+impl From<Data> for google_ai_rs::Data {
+    fn from(value: Data) -> Self {
+        match value {
             Data::Text{ text } => google_ai_rs::Data::Text(text),
             Data::InlineData(v) => google_ai_rs::Data::InlineData(v.into()),
             Data::FunctionCall(v) => google_ai_rs::Data::FunctionCall(v.into()),
@@ -328,50 +330,187 @@ impl From<google_ai_rs::Part> for Part {
     }
 }
 
-impl Into<google_ai_rs::Part> for Part {
-    fn into(self) -> google_ai_rs::Part {
+impl From<Part> for google_ai_rs::Part {
+    fn from(value: Part) -> Self {
         google_ai_rs::Part {
-            data: self.data.map(|v| v.into()),
+            data: value.data.map(|v| v.into()),
+        }
+    }
+}
+
+/// Author of a `Content`
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Model,
+    /// Function responses to the model's calls
+    Tool,
+    /// Messages of the server itself, such as errors; sent to clients but never kept in history
+    System,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Model => "model",
+            Role::Tool => "tool",
+            Role::System => "system",
         }
     }
+
+    /// Role as the Gemini API names it, which calls the author of function responses
+    /// `function` rather than `tool`
+    pub fn api_name(self) -> &'static str {
+        match self {
+            Role::Tool => "function",
+            role => role.as_str(),
+        }
+    }
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct Content {
     pub parts: Vec<Part>,
-    pub role: String,
+    pub role: Role,
 }
 
 impl Content {
     pub fn system(parts: Vec<Part>) -> Self {
         Self {
             parts,
-            role: "system".to_string(),
+            role: Role::System,
+        }
+    }
+
+    pub fn model(parts: Vec<Part>) -> Self {
+        Self {
+            parts,
+            role: Role::Model,
         }
     }
 
     pub fn tool(parts: Vec<Part>) -> Self {
         Self {
             parts,
-            role: "tool".to_string(),
+            role: Role::Tool,
         }
     }
 }
 
 impl From<google_ai_rs::proto::Content> for Content {
+    /// Contents from the API are the model's, whatever role they're labeled with
     fn from(value: google_ai_rs::Content) -> Self {
         Self {
             parts: value.parts.into_iter().map(|v| v.into()).collect(),
-            role: value.role,
+            role: Role::Model,
         }
     }
 }
 
-impl Into<google_ai_rs::proto::Content> for Content {
-    fn into(self) -> google_ai_rs::proto::Content {
+impl From<Content> for google_ai_rs::proto::Content {
+    fn from(value: Content) -> Self {
         google_ai_rs::proto::Content {
-            parts: self.parts.into_iter().map(|v| v.into()).collect(),
-            role: self.role,
+            parts: value.parts.into_iter().map(|v| v.into()).collect(),
+            role: value.role.api_name().to_string(),
+        }
+    }
+}
+
+
+#[derive(Serialize, Clone)]
+pub struct Schema {
+    pub r#type: String,
+    pub format: String,
+    pub description: String,
+    pub nullable: bool,
+    pub r#enum: Vec<String>,
+    pub items: Option<Box<Schema>>,
+    pub max_items: i64,
+    pub min_items: i64,
+    pub properties: HashMap<String, Schema>,
+    pub required: Vec<String>,
+}
+
+impl From<google_ai_rs::Schema> for Schema {
+    fn from(value: google_ai_rs::Schema) -> Self {
+        let r#type = google_ai_rs::proto::Type::try_from(value.r#type)
+            .unwrap_or(google_ai_rs::proto::Type::Unspecified);
+
+        Self {
+            r#type: r#type.as_str_name().to_string(),
+            format: value.format,
+            description: value.description,
+            nullable: value.nullable,
+            r#enum: value.r#enum,
+            items: value.items.map(|v| Box::new((*v).into())),
+            max_items: value.max_items,
+            min_items: value.min_items,
+            properties: value
+                .properties
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect(),
+            required: value.required,
         }
     }
 }
+
+#[derive(Serialize, Clone)]
+pub struct FunctionDeclaration {
+    pub name: String,
+    pub description: String,
+    pub parameters: Option<Schema>,
+    pub response: Option<Schema>,
+}
+
+impl From<google_ai_rs::proto::FunctionDeclaration> for FunctionDeclaration {
+    fn from(value: google_ai_rs::proto::FunctionDeclaration) -> Self {
+        Self {
+            name: value.name,
+            description: value.description,
+            parameters: value.parameters.map(|v| v.into()),
+            response: value.response.map(|v| v.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Vec<Part> {
+        vec![Part::new(Data::from(s.to_string()))]
+    }
+
+    #[test]
+    fn tool_contents_reach_the_api_as_function() {
+        let response = FunctionResponse {
+            id: "call_0_0".to_string(),
+            name: "read_fs".to_string(),
+            response: None,
+        };
+        let content: google_ai_rs::proto::Content = Content::tool(vec![Part::new(Data::FunctionResponse(response))]).into();
+        assert_eq!(content.role, "function");
+        assert!(matches!(
+            &content.parts[0].data,
+            Some(google_ai_rs::proto::part::Data::FunctionResponse(response)) if response.id == "call_0_0"
+        ));
+    }
+
+    #[test]
+    fn other_roles_keep_their_names() {
+        let model: google_ai_rs::proto::Content = Content::model(text("a")).into();
+        let user: google_ai_rs::proto::Content = Content { parts: text("b"), role: Role::User }.into();
+        assert_eq!(model.role, "model");
+        assert_eq!(user.role, "user");
+        assert_eq!(Role::Tool.as_str(), "tool");
+    }
+}
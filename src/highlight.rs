@@ -0,0 +1,232 @@
+/// How a language writes what gets highlighted
+struct Syntax {
+    line_comments: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    /// Whether a tripled quote starts a string running to the next tripled quote
+    triple_quotes: bool,
+    keywords: &'static [&'static str],
+}
+
+const C_KEYWORDS: &[&str] = &[
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else",
+    "enum", "extern", "float", "for", "goto", "if", "inline", "int", "long", "register",
+    "return", "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union",
+    "unsigned", "void", "volatile", "while", "bool", "true", "false", "NULL",
+];
+
+const CPP_KEYWORDS: &[&str] = &[
+    "auto", "bool", "break", "case", "catch", "char", "class", "const", "constexpr",
+    "continue", "default", "delete", "do", "double", "else", "enum", "explicit", "extern",
+    "false", "float", "for", "friend", "if", "inline", "int", "long", "mutable", "namespace",
+    "new", "noexcept", "nullptr", "operator", "private", "protected", "public", "return",
+    "short", "signed", "sizeof", "static", "struct", "switch", "template", "this", "throw",
+    "true", "try", "typedef", "typename", "union", "unsigned", "using", "virtual", "void",
+    "volatile", "while",
+];
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum",
+    "extern", "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move",
+    "mut", "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true",
+    "type", "unsafe", "use", "where", "while",
+];
+
+const GO_KEYWORDS: &[&str] = &[
+    "break", "case", "chan", "const", "continue", "default", "defer", "else", "fallthrough",
+    "false", "for", "func", "go", "goto", "if", "import", "interface", "map", "nil", "package",
+    "range", "return", "select", "struct", "switch", "true", "type", "var",
+];
+
+const JAVA_KEYWORDS: &[&str] = &[
+    "abstract", "boolean", "break", "byte", "case", "catch", "char", "class", "continue",
+    "default", "do", "double", "else", "enum", "extends", "false", "final", "finally", "float",
+    "for", "if", "implements", "import", "instanceof", "int", "interface", "long", "new",
+    "null", "package", "private", "protected", "public", "return", "short", "static", "super",
+    "switch", "synchronized", "this", "throw", "throws", "true", "try", "void", "while",
+];
+
+const JS_KEYWORDS: &[&str] = &[
+    "async", "await", "break", "case", "catch", "class", "const", "continue", "default",
+    "delete", "do", "else", "export", "extends", "false", "finally", "for", "from", "function",
+    "if", "import", "in", "instanceof", "let", "new", "null", "of", "return", "super", "switch",
+    "this", "throw", "true", "try", "typeof", "undefined", "var", "void", "while", "yield",
+    // TypeScript
+    "declare", "enum", "implements", "interface", "keyof", "namespace", "private", "protected",
+    "public", "readonly", "type",
+];
+
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "False", "finally", "for", "from", "global", "if", "import", "in", "is",
+    "lambda", "None", "nonlocal", "not", "or", "pass", "raise", "return", "True", "try",
+    "while", "with", "yield",
+];
+
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "return", "then", "until", "while",
+];
+
+const LUA_KEYWORDS: &[&str] = &[
+    "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "goto", "if",
+    "in", "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+];
+
+const RUBY_KEYWORDS: &[&str] = &[
+    "begin", "break", "case", "class", "def", "do", "else", "elsif", "end", "ensure", "false",
+    "for", "if", "in", "module", "next", "nil", "not", "or", "and", "redo", "rescue", "retry",
+    "return", "self", "super", "then", "true", "unless", "until", "when", "while", "yield",
+];
+
+const DATA_KEYWORDS: &[&str] = &["true", "false", "null"];
+
+const fn c_like(keywords: &'static [&'static str], quotes: &'static [char]) -> Syntax {
+    Syntax {
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        quotes,
+        triple_quotes: false,
+        keywords,
+    }
+}
+
+const fn hash_commented(keywords: &'static [&'static str], triple_quotes: bool) -> Syntax {
+    Syntax {
+        line_comments: &["#"],
+        block_comment: None,
+        quotes: &['"', '\''],
+        triple_quotes,
+        keywords,
+    }
+}
+
+/// Syntax of a language as `language::from_path` names it; `None` for those not highlighted
+fn syntax_of(language: &str) -> Option<Syntax> {
+    Some(match language {
+        "c" => c_like(C_KEYWORDS, &['"', '\'']),
+        "cpp" => c_like(CPP_KEYWORDS, &['"', '\'']),
+        // `'` also starts lifetimes, which a quote scan would run away with
+        "rust" => c_like(RUST_KEYWORDS, &['"']),
+        "go" => c_like(GO_KEYWORDS, &['"', '\'', '`']),
+        "java" | "kotlin" | "csharp" | "swift" => c_like(JAVA_KEYWORDS, &['"', '\'']),
+        "javascript" | "typescript" => c_like(JS_KEYWORDS, &['"', '\'', '`']),
+        "json" => c_like(DATA_KEYWORDS, &['"']),
+        "python" => hash_commented(PYTHON_KEYWORDS, true),
+        "shell" => hash_commented(SHELL_KEYWORDS, false),
+        "ruby" => hash_commented(RUBY_KEYWORDS, false),
+        "toml" | "yaml" => hash_commented(DATA_KEYWORDS, false),
+        "lua" => Syntax {
+            line_comments: &["--"],
+            block_comment: None,
+            quotes: &['"', '\''],
+            triple_quotes: false,
+            keywords: LUA_KEYWORDS,
+        },
+        _ => return None,
+    })
+}
+
+fn escape(text: &str, html: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            c => html.push(c),
+        }
+    }
+}
+
+fn push_token(class: &str, text: &str, html: &mut String) {
+    html.push_str("<span class=\"hl-");
+    html.push_str(class);
+    html.push_str("\">");
+    escape(text, html);
+    html.push_str("</span>");
+}
+
+/// Length of the string starting at `rest` with `quote`: to the closing quote, or to the end
+/// of the line if there's none
+fn string_len(rest: &str, quote: char, triple: bool) -> usize {
+    if triple {
+        let delimiter = quote.to_string().repeat(3);
+        return match rest[3..].find(&delimiter) {
+            Some(end) => 3 + end + 3,
+            None => rest.len(),
+        };
+    }
+
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '\n' if quote != '`' => return i,
+            c if c == quote => return i + c.len_utf8(),
+            _ => {}
+        }
+    }
+    rest.len()
+}
+
+/// `text` as HTML with comments, strings, numbers and keywords of `language` in
+/// `<span class="hl-...">`; `None` if the language isn't one highlighted.
+///
+/// A lexer this small gets some things wrong, such as nested comments or raw strings,
+/// but never loses or reorders any of the text.
+pub fn to_html(text: &str, language: &str) -> Option<String> {
+    let syntax = syntax_of(language)?;
+    let mut html = String::with_capacity(text.len() * 2);
+
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let c = rest.chars().next().unwrap();
+
+        let len = if syntax.line_comments.iter().any(|prefix| rest.starts_with(prefix)) {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            push_token("comment", &rest[..len], &mut html);
+            len
+        } else if let Some((start, end)) = syntax.block_comment
+            && rest.starts_with(start)
+        {
+            let len = match rest[start.len()..].find(end) {
+                Some(at) => start.len() + at + end.len(),
+                None => rest.len(),
+            };
+            push_token("comment", &rest[..len], &mut html);
+            len
+        } else if syntax.quotes.contains(&c) {
+            let triple = syntax.triple_quotes && rest.starts_with(&c.to_string().repeat(3));
+            let len = string_len(rest, c, triple);
+            push_token("string", &rest[..len], &mut html);
+            len
+        } else if c.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '.'))
+                .unwrap_or(rest.len());
+            push_token("number", &rest[..len], &mut html);
+            len
+        } else if c.is_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            if syntax.keywords.contains(&word) {
+                push_token("keyword", word, &mut html);
+            } else {
+                escape(word, &mut html);
+            }
+            len
+        } else {
+            escape(&rest[..c.len_utf8()], &mut html);
+            c.len_utf8()
+        };
+
+        i += len;
+    }
+
+    Some(html)
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+
+/// (extension, language)
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cxx", "cpp"),
+    ("hpp", "cpp"),
+    ("hxx", "cpp"),
+    ("cs", "csharp"),
+    ("go", "go"),
+    ("java", "java"),
+    ("kt", "kotlin"),
+    ("swift", "swift"),
+    ("py", "python"),
+    ("rb", "ruby"),
+    ("php", "php"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("jsx", "javascript"),
+    ("ts", "typescript"),
+    ("tsx", "typescript"),
+    ("lua", "lua"),
+    ("sh", "shell"),
+    ("bash", "shell"),
+    ("zsh", "shell"),
+    ("ps1", "powershell"),
+    ("sql", "sql"),
+    ("html", "html"),
+    ("htm", "html"),
+    ("css", "css"),
+    ("scss", "scss"),
+    ("md", "markdown"),
+    ("json", "json"),
+    ("toml", "toml"),
+    ("yaml", "yaml"),
+    ("yml", "yaml"),
+    ("xml", "xml"),
+    ("svg", "xml"),
+    ("ini", "ini"),
+    ("cmake", "cmake"),
+];
+
+/// (file name, language), for files conventionally without an extension
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("Makefile", "makefile"),
+    ("makefile", "makefile"),
+    ("GNUmakefile", "makefile"),
+    ("Dockerfile", "dockerfile"),
+    ("CMakeLists.txt", "cmake"),
+];
+
+/// Best-effort guess of a file's programming or markup language from its name
+pub fn from_path(path: impl AsRef<Path>) -> Option<&'static str> {
+    let path = path.as_ref();
+
+    let name = path.file_name()?.to_str()?;
+    if let Some(&(_, language)) = FILE_NAMES.iter().find(|(n, _)| *n == name) {
+        return Some(language);
+    }
+
+    let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| *e == ext)
+        .map(|&(_, language)| language)
+}
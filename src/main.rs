@@ -1,44 +1,232 @@
-#![feature(coroutines)]
-#![feature(gen_blocks)]
-#![feature(async_iterator)]
-#![feature(str_as_str)]
-#![feature(associated_type_defaults)]
-
 mod chat;
+mod config;
 mod defs;
+mod highlight;
+mod language;
+mod mcp;
+mod mime;
+mod reloadable;
+#[cfg(test)]
+mod testing;
+mod throttle;
 mod tools;
+mod transcript;
+mod undo;
+mod utf8;
 
-use crate::chat::{add_chat, process_chat};
+use crate::chat::{process_chat, ChooseError, FeedbackError, FrameSender, Framing, Generation, Rating};
+use crate::config::Config;
 use crate::defs::*;
-use crate::tools::{read_fs_decl, search_fs_decl};
+use crate::reloadable::Reloadable;
+use crate::transcript::ToolDetail;
+use crate::undo::UndoError;
 use bytes::Bytes;
 use dotenv::dotenv;
-use google_ai_rs::{Client, GenerativeModel, Tool};
+use google_ai_rs::{Client, GenerationConfig, GenerativeModel, Tool};
 use http::{Method, Request, Response, StatusCode, header};
 use http_body_util::combinators::BoxBody;
-use http_body_util::{BodyExt, Full, StreamBody};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited, StreamBody};
 use hyper::body::Incoming;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{TokioIo, TokioTimer};
+use hyper_util::server::graceful::GracefulShutdown;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::env::var_os;
 use std::error::Error;
+use std::fs;
 use std::net::SocketAddr;
-use std::sync::OnceLock;
-use tokio::net::TcpListener;
+use std::os::unix::fs::FileTypeExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::mpsc::channel;
+use tokio::sync::Notify;
 use tokio_stream::wrappers::ReceiverStream;
 
 type ResponseResult = Result<Response<BoxBody<Bytes, Infallible>>, Box<dyn Error + Send + Sync>>;
 
+/// Configuration for new requests; `POST /admin/reload` swaps in a fresh one
+static CONFIG: Reloadable<Config> = Reloadable::new();
 static CLIENT: OnceLock<Client> = OnceLock::new();
-static MODEL: OnceLock<GenerativeModel> = OnceLock::new();
+/// Environment variables the process was started with, before `.env` added to them
+static STARTUP_ENV: OnceLock<HashMap<String, String>> = OnceLock::new();
+static MODEL: Reloadable<GenerativeModel> = Reloadable::new();
+/// Models of `Config::allowed_models`, by name
+static MODELS: Reloadable<HashMap<String, Arc<GenerativeModel>>> = Reloadable::new();
+
+const DEFAULT_MODEL: &str = "gemini-2.5-pro";
+
+/// Notified to begin graceful shutdown, like SIGTERM
+static SHUTDOWN: Notify = Notify::const_new();
+
+/// How long shutdown waits for open connections to finish
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// RFC 7807 problem details object
+#[derive(Serialize)]
+struct Problem {
+    r#type: &'static str,
+    title: &'static str,
+    status: u16,
+    detail: String,
+    /// Extension members particular to the problem
+    #[serde(flatten)]
+    extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+fn problem(status: StatusCode, detail: impl ToString) -> Response<BoxBody<Bytes, Infallible>> {
+    problem_with(status, detail, serde_json::Map::new())
+}
+
+fn problem_with(
+    status: StatusCode,
+    detail: impl ToString,
+    extensions: serde_json::Map<String, serde_json::Value>,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    problem_response(status, problem_json(status, detail, extensions))
+}
+
+fn problem_json(
+    status: StatusCode,
+    detail: impl ToString,
+    extensions: serde_json::Map<String, serde_json::Value>,
+) -> Bytes {
+    let problem = Problem {
+        r#type: "about:blank",
+        title: status.canonical_reason().unwrap_or("Unknown"),
+        status: status.as_u16(),
+        detail: detail.to_string(),
+        extensions,
+    };
+    Bytes::from(serde_json::to_string(&problem).unwrap())
+}
+
+fn problem_response(status: StatusCode, json: Bytes) -> Response<BoxBody<Bytes, Infallible>> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/problem+json")
+        .body(Full::from(json).boxed())
+        .unwrap()
+}
+
+/// Picks the media type in `offered` most preferred by the request's `Accept` header.
+///
+/// Falls back to the first offered type when `Accept` is absent or matches nothing offered.
+fn negotiate<B>(req: &Request<B>, offered: &[&'static str]) -> &'static str {
+    let Some(accept) = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+    else {
+        return offered[0];
+    };
+
+    let mut best: Option<(&'static str, f32)> = None;
+
+    for range in accept.split(',') {
+        let mut params = range.split(';').map(str::trim);
+        let media = params.next().unwrap_or_default();
+        let q = params
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        let matched = offered.iter().find(|o| {
+            media == "*/*"
+                || media == **o
+                || media
+                    .strip_suffix("/*")
+                    .is_some_and(|t| o.split('/').next() == Some(t))
+        });
+
+        if let Some(o) = matched
+            && q > 0.0
+            && best.is_none_or(|(_, best_q)| q > best_q)
+        {
+            best = Some((o, q));
+        }
+    }
+
+    best.map(|(o, _)| o).unwrap_or(offered[0])
+}
+
+/// Byte offset in `body` where parsing failed, from the line and column serde_json reports
+fn json_error_offset(body: &[u8], e: &serde_json::Error) -> Option<usize> {
+    if e.line() == 0 {
+        return None;
+    }
+
+    let line_start = match e.line() {
+        1 => 0,
+        line => {
+            body.iter()
+                .enumerate()
+                .filter(|(_, b)| **b == b'\n')
+                .nth(line - 2)?
+                .0
+                + 1
+        }
+    };
+    Some((line_start + e.column().saturating_sub(1)).min(body.len()))
+}
+
+/// Problem for a request body that isn't valid JSON of the `expected` type
+fn invalid_body(
+    body: &[u8],
+    e: serde_json::Error,
+    expected: &str,
+) -> Response<BoxBody<Bytes, Infallible>> {
+    let mut extensions = serde_json::Map::new();
+    extensions.insert("expected".to_string(), expected.into());
+    if let Some(offset) = json_error_offset(body, &e) {
+        extensions.insert("offset".to_string(), offset.into());
+    }
+
+    problem_with(StatusCode::BAD_REQUEST, e, extensions)
+}
+
+async fn get_chat(req: Request<Incoming>) -> ResponseResult {
+    let offered = ["application/json", "text/plain", "application/x-ndjson"];
+    let media = negotiate(&req, &offered);
+
+    if media == "application/x-ndjson" {
+        return stream_history(Framing::Ndjson);
+    }
+
+    if media == "text/plain" {
+        let tools = match requested_tool_detail(&req) {
+            Ok(tools) => tools,
+            Err(e) => return Ok(problem(StatusCode::BAD_REQUEST, e)),
+        };
+
+        let chat = chat::get_chat().await;
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Full::from(Bytes::from(transcript::render(&chat, tools))).boxed())
+            .unwrap());
+    }
+
+    let pretty = match requested_pretty(&req) {
+        Ok(pretty) => pretty,
+        Err(e) => return Ok(problem(StatusCode::BAD_REQUEST, e)),
+    };
 
-async fn get_chat() -> ResponseResult {
     let chat = chat::get_chat().await;
-    let json = serde_json::to_string(&chat)?;
+
+    let json = match pretty {
+        true => serde_json::to_string_pretty(&chat)?,
+        false => serde_json::to_string(&chat)?,
+    };
     Ok(Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
@@ -47,41 +235,719 @@ async fn get_chat() -> ResponseResult {
         .unwrap())
 }
 
-async fn post_chat(req: Request<Incoming>) -> ResponseResult {
+fn stream_history(framing: Framing) -> ResponseResult {
+    let (sender, receiver) = channel(256);
+    tokio::spawn(chat::replay_chat(FrameSender::new(sender, framing)));
+
+    let stream = ReceiverStream::new(receiver);
+    let stream_body = StreamBody::new(stream);
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, framing.content_type())
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(stream_body.boxed())?)
+}
+
+/// Function response `id` whole, for a client to see what a preview left out
+async fn get_tool_result(id: &str) -> ResponseResult {
+    let Some(resp) = chat::get_tool_result(id).await else {
+        return Ok(problem(StatusCode::NOT_FOUND, format!("No tool result with id '{}'", id)));
+    };
+
+    let json = serde_json::to_string(&resp)?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::from(Bytes::from(json)).boxed())?)
+}
+
+async fn delete_chat() -> ResponseResult {
+    chat::clear_chat().await;
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Full::new(Bytes::new()).boxed())?)
+}
+
+async fn delete_chat_message(index: &str) -> ResponseResult {
+    let Ok(index) = index.parse::<usize>() else {
+        return Ok(problem(StatusCode::BAD_REQUEST, format!("'{}' is not a message index", index)));
+    };
+
+    if !chat::delete_message(index).await {
+        return Ok(problem(StatusCode::NOT_FOUND, format!("No message at index {}", index)));
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Full::new(Bytes::new()).boxed())?)
+}
+
+async fn get_chat_replay() -> ResponseResult {
+    stream_history(Framing::Sse)
+}
+
+#[derive(Serialize)]
+struct Features {
+    allow_destructive: bool,
+    no_tools: bool,
+}
+
+/// Names of the models a request may choose, the default first
+fn model_names() -> Vec<String> {
+    let mut names = vec![DEFAULT_MODEL.to_string()];
+    for name in &CONFIG.get().unwrap().allowed_models {
+        if !names.contains(name) {
+            names.push(name.clone());
+        }
+    }
+    names
+}
+
+/// Value of query parameter `name`, decoded as forms encode it; the first if it's repeated
+fn query_param<B>(req: &Request<B>, name: &str) -> Option<String> {
+    let decode = |s: &str| percent_decode_str(&s.replace('+', " ")).decode_utf8_lossy().into_owned();
+
+    req.uri().query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (decode(key) == name).then(|| decode(value))
+    })
+}
+
+/// How a transcript shows tool calls per the `tools` query parameter, one line each without one
+fn requested_tool_detail<B>(req: &Request<B>) -> Result<ToolDetail, String> {
+    let value = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("tools="));
+
+    match value {
+        None => Ok(ToolDetail::Summary),
+        Some(value) => ToolDetail::parse(value).ok_or_else(|| {
+            format!("Unknown tools '{}'; expected 'full', 'summary' or 'omit'", value)
+        }),
+    }
+}
+
+/// Whether the `pretty` query parameter asks for indented JSON; compact without one
+fn requested_pretty<B>(req: &Request<B>) -> Result<bool, String> {
+    let value = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("pretty="));
+
+    match value {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(value) => Err(format!("Unknown pretty '{}'; expected 'true' or 'false'", value)),
+    }
+}
+
+/// The model named by the `model` query parameter, or the default without one
+fn requested_model<B>(req: &Request<B>) -> Result<Arc<GenerativeModel<'static>>, String> {
+    let name = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("model="));
+
+    match name {
+        None => Ok(MODEL.get().unwrap()),
+        Some(DEFAULT_MODEL) => Ok(MODEL.get().unwrap()),
+        Some(name) => MODELS
+            .get()
+            .unwrap()
+            .get(name)
+            .cloned()
+            .ok_or_else(|| format!("Model '{}' is not allowed", name)),
+    }
+}
+
+#[derive(Serialize)]
+struct Capabilities {
+    model: String,
+    /// Models that can be chosen with `POST /chat?model=`
+    models: Vec<String>,
+    tools: Vec<FunctionDeclaration>,
+    features: Features,
+}
+
+async fn get_capabilities() -> ResponseResult {
+    let model = MODEL.get().unwrap();
+    let config = CONFIG.get().unwrap();
+
+    let capabilities = Capabilities {
+        model: model.full_name().to_string(),
+        models: model_names(),
+        tools: model
+            .tools
+            .iter()
+            .flatten()
+            .flat_map(|tool| tool.function_declarations.iter().cloned())
+            .map(Into::into)
+            .collect(),
+        features: Features {
+            allow_destructive: config.allow_destructive,
+            no_tools: config.no_tools,
+        },
+    };
+
+    let json = serde_json::to_string(&capabilities)?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::CACHE_CONTROL, "no-cache")
+        .body(Full::from(Bytes::from(json)).boxed())
+        .unwrap())
+}
+
+async fn post_fs_undo() -> ResponseResult {
+    let undone = match tokio::task::spawn_blocking(undo::undo).await? {
+        Ok(undone) => undone,
+        Err(UndoError::Io(e)) => return Ok(problem(StatusCode::INTERNAL_SERVER_ERROR, e)),
+        Err(UndoError::Nothing) => return Ok(problem(StatusCode::CONFLICT, "Nothing to undo")),
+        Err(UndoError::NotUndoable { operation, path }) => {
+            return Ok(problem(
+                StatusCode::CONFLICT,
+                format!("The last {} to '{}' can't be undone, nor anything before it", operation, path),
+            ));
+        }
+    };
+
+    let json = serde_json::to_string(&undone)?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::from(Bytes::from(json)).boxed())
+        .unwrap())
+}
+
+#[derive(Serialize)]
+struct Version {
+    version: &'static str,
+    commit: &'static str,
+    built_at: &'static str,
+}
+
+async fn get_version() -> ResponseResult {
+    let version = Version {
+        version: env!("CARGO_PKG_VERSION"),
+        commit: env!("YAS_GIT_COMMIT"),
+        built_at: env!("YAS_BUILT_AT"),
+    };
+
+    let json = serde_json::to_string(&version)?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::from(Bytes::from(json)).boxed())
+        .unwrap())
+}
+
+#[derive(Deserialize)]
+struct Approval {
+    id: String,
+    approved: bool,
+}
+
+async fn post_chat_approve(req: Request<Incoming>) -> ResponseResult {
     let body = req.collect().await?.to_bytes();
+    let approval = match serde_json::from_slice::<Approval>(&body) {
+        Ok(approval) => approval,
+        Err(e) => return Ok(invalid_body(&body, e, "Approval")),
+    };
+
+    if !chat::approve(&approval.id, approval.approved) {
+        return Ok(problem(
+            StatusCode::NOT_FOUND,
+            format!("No tool call '{}' is waiting for approval", approval.id),
+        ));
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Full::new(Bytes::new()).boxed())?)
+}
+
+#[derive(Deserialize)]
+struct Feedback {
+    index: usize,
+    rating: Rating,
+    comment: Option<String>,
+}
+
+async fn post_chat_feedback(req: Request<Incoming>) -> ResponseResult {
+    let body = req.collect().await?.to_bytes();
+    let feedback = match serde_json::from_slice::<Feedback>(&body) {
+        Ok(feedback) => feedback,
+        Err(e) => return Ok(invalid_body(&body, e, "Feedback")),
+    };
+
+    match chat::record_feedback(feedback.index, feedback.rating, feedback.comment.as_deref()).await {
+        Ok(()) => {}
+        Err(FeedbackError::NotFound) => {
+            return Ok(problem(
+                StatusCode::NOT_FOUND,
+                format!("No message at index {}", feedback.index),
+            ));
+        }
+        Err(FeedbackError::NotModel) => {
+            return Ok(problem(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Message {} isn't a model response", feedback.index),
+            ));
+        }
+        Err(FeedbackError::Io(e)) => {
+            return Ok(problem(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Cannot record feedback: {}", e),
+            ));
+        }
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Full::new(Bytes::new()).boxed())?)
+}
+
+#[derive(Deserialize)]
+struct Choice {
+    index: i32,
+}
+
+async fn post_chat_choose(req: Request<Incoming>) -> ResponseResult {
+    let body = req.collect().await?.to_bytes();
+    let choice = match serde_json::from_slice::<Choice>(&body) {
+        Ok(choice) => choice,
+        Err(e) => return Ok(invalid_body(&body, e, "Choice")),
+    };
+
+    match chat::choose_candidate(choice.index).await {
+        Ok(()) => {}
+        Err(ChooseError::NotFound) => {
+            return Ok(problem(
+                StatusCode::NOT_FOUND,
+                format!("No candidate {} is waiting to be chosen", choice.index),
+            ));
+        }
+        Err(ChooseError::Stale) => {
+            return Ok(problem(
+                StatusCode::CONFLICT,
+                "The conversation has changed since the candidates were generated",
+            ));
+        }
+        Err(ChooseError::CallsTools) => {
+            return Ok(problem(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("Candidate {} calls tools, which were never run", choice.index),
+            ));
+        }
+    }
+
+    Ok(Response::builder()
+        .status(StatusCode::NO_CONTENT)
+        .body(Full::new(Bytes::new()).boxed())?)
+}
+
+/// Compares in time independent of where the inputs differ
+fn secure_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn is_admin<B>(req: &Request<B>, token: &str) -> bool {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .is_some_and(|v| secure_eq(v.as_bytes(), token.as_bytes()))
+}
+
+async fn post_admin_shutdown(req: Request<Incoming>, token: &str) -> ResponseResult {
+    if !is_admin(&req, token) {
+        return Ok(problem(StatusCode::FORBIDDEN, "Invalid admin token"));
+    }
+
+    SHUTDOWN.notify_one();
+
+    Ok(Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .body(Full::new(Bytes::new()).boxed())?)
+}
+
+/// Variables as a fresh start would see them: the environment the process started with,
+/// then `.env` as it is now for those not set there
+// `dotenv_iter` is deprecated in favour of loading into the process environment, which would
+// leave the variables of the first load in place
+#[allow(deprecated)]
+fn reload_vars() -> Result<HashMap<String, String>, String> {
+    let mut vars = HashMap::new();
+    if let Ok(entries) = dotenv::dotenv_iter() {
+        for entry in entries {
+            let (name, value) = entry.map_err(|e| format!("Invalid .env file: {}", e))?;
+            vars.insert(name, value);
+        }
+    }
+
+    vars.extend(STARTUP_ENV.get().unwrap().clone());
+    Ok(vars)
+}
+
+/// Reads the configuration anew, as from `reload_vars`
+fn reload_config() -> Result<Config, String> {
+    let vars = reload_vars()?;
+    Config::from_vars(&|name| vars.get(name).cloned())
+}
+
+/// Swaps in a fresh configuration, with the models and command tools built from it, for new
+/// requests; generations already running keep their model
+async fn post_admin_reload(req: Request<Incoming>, token: &str) -> ResponseResult {
+    if !is_admin(&req, token) {
+        return Ok(problem(StatusCode::FORBIDDEN, "Invalid admin token"));
+    }
+
+    let config = match reload_config() {
+        Ok(config) => config,
+        Err(e) => return Ok(problem(StatusCode::BAD_REQUEST, e)),
+    };
+    if let Err(e) = tools::load_command_tools(&config) {
+        return Ok(problem(StatusCode::BAD_REQUEST, e));
+    }
+
+    let effective = format!("{:#?}", config);
+    CONFIG.replace(config);
+    load_models(&CONFIG.get().unwrap());
+
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Full::from(Bytes::from(effective)).boxed())?)
+}
+
+/// Checks inline blobs of `chat` against the configured size caps
+fn check_attachments(chat: &Content, config: &Config) -> Result<(), String> {
+    let mut total = 0;
+
+    for part in &chat.parts {
+        let Some(Data::InlineData(blob)) = &part.data else {
+            continue;
+        };
+
+        if blob.data.len() > config.max_blob_bytes {
+            return Err(format!(
+                "Inline '{}' blob of {} bytes exceeds the limit of {} bytes",
+                blob.mime_type,
+                blob.data.len(),
+                config.max_blob_bytes
+            ));
+        }
+
+        total += blob.data.len();
+        if total > config.max_attachment_bytes {
+            return Err(format!(
+                "Inline blobs exceed the total limit of {} bytes",
+                config.max_attachment_bytes
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Refuses inline blobs whose content gives their declared type the lie
+fn check_attachment_types(chat: &Content) -> Result<(), String> {
+    for part in &chat.parts {
+        let Some(Data::InlineData(blob)) = &part.data else {
+            continue;
+        };
+
+        if let Err(e) = mime::check_declared(&blob.mime_type, &blob.data) {
+            eprintln!("warn: rejected inline blob: {}", e);
+            return Err(e);
+        }
+    }
+
+    Ok(())
+}
+
+/// `POST /chat` streams whose generation is still running
+static ACTIVE_STREAMS: AtomicUsize = AtomicUsize::new(0);
+
+/// A place among `ACTIVE_STREAMS`, given back when dropped, even by a panicking task
+struct StreamSlot;
+
+impl StreamSlot {
+    /// Takes a place unless `max` streams are already active
+    fn acquire(max: Option<usize>) -> Option<StreamSlot> {
+        let max = max.unwrap_or(usize::MAX);
+        ACTIVE_STREAMS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| (n < max).then_some(n + 1))
+            .ok()
+            .map(|_| StreamSlot)
+    }
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        ACTIVE_STREAMS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// The chat posted in `req`, checked against `config`, and the generation it's to have
+/// if `YAS_REJECT_BUSY` has it begin at once; the response refusing it otherwise
+async fn accept_chat<B>(
+    req: Request<B>,
+    config: &Config,
+) -> Result<(Content, Option<Generation>), Response<BoxBody<Bytes, Infallible>>>
+where
+    B: hyper::body::Body<Data = Bytes>,
+    B::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    let body = match Limited::new(req.into_body(), config.max_body_bytes).collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(e) if e.is::<LengthLimitError>() => {
+            return Err(problem(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Body exceeds the limit of {} bytes", config.max_body_bytes),
+            ));
+        }
+        Err(e) => return Err(problem(StatusCode::BAD_REQUEST, format!("Cannot read body: {}", e))),
+    };
     let chat = match serde_json::from_slice::<Content>(&body) {
         Ok(chat) => chat,
+        Err(e) => return Err(invalid_body(&body, e, "Content")),
+    };
+
+    if chat.parts.len() > config.max_parts {
+        return Err(problem(
+            StatusCode::BAD_REQUEST,
+            format!("Message has {} parts; at most {} are accepted", chat.parts.len(), config.max_parts),
+        ));
+    }
+
+    if chat.role != Role::User {
+        return Err(problem(
+            StatusCode::BAD_REQUEST,
+            format!("Role '{}' can't be posted; only 'user' messages can", chat.role),
+        ));
+    }
+
+    if let Err(e) = check_attachments(&chat, config) {
+        return Err(problem(StatusCode::PAYLOAD_TOO_LARGE, e));
+    }
+
+    if let Err(e) = check_attachment_types(&chat) {
+        return Err(problem(StatusCode::UNSUPPORTED_MEDIA_TYPE, e));
+    }
+
+    // Without `YAS_REJECT_BUSY`, the generation waits its turn once the stream is open
+    let generation = match config.reject_busy {
+        true => match Generation::try_begin() {
+            Some(generation) => Some(generation),
+            None => {
+                return Err(problem(
+                    StatusCode::CONFLICT,
+                    "A reply is already being generated; try again once it's done",
+                ));
+            }
+        },
+        false => None,
+    };
+
+    Ok((chat, generation))
+}
+
+async fn post_chat(req: Request<Incoming>) -> ResponseResult {
+    let model = match requested_model(&req) {
+        Ok(model) => model,
         Err(e) => {
-            return Ok(Response::builder()
-                .status(StatusCode::BAD_REQUEST)
-                .body(Full::new(Bytes::from(e.to_string())).boxed())?);
+            let mut extensions = serde_json::Map::new();
+            extensions.insert("allowed".to_string(), model_names().into());
+            return Ok(problem_with(StatusCode::BAD_REQUEST, e, extensions));
         }
     };
 
+    let framing = match negotiate(&req, &["text/event-stream", "application/x-ndjson"]) {
+        "application/x-ndjson" => Framing::Ndjson,
+        _ => Framing::Sse,
+    };
+
+    let (chat, generation) = match accept_chat(req, &CONFIG.get().unwrap()).await {
+        Ok(accepted) => accepted,
+        Err(response) => return Ok(response),
+    };
+
+    let Some(slot) = StreamSlot::acquire(CONFIG.get().unwrap().max_active_streams) else {
+        return Ok(problem(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many chats are being generated at once; try again later",
+        ));
+    };
+
     let (sender, receiver) = channel(256);
+    let sender = FrameSender::new(sender, framing);
+    if let Some(retry) = CONFIG.get().unwrap().sse_retry {
+        sender.hint_retry(retry);
+    }
 
     tokio::spawn(async move {
-        add_chat(chat).await;
-        process_chat(sender).await;
+        let _slot = slot;
+        let generation = match generation {
+            Some(generation) => generation,
+            None => Generation::wait().await,
+        };
+        process_chat(generation, chat, sender, model.as_ref()).await;
     });
 
+    let receiver = match CONFIG.get().unwrap().max_stream {
+        Some(max) => chat::limit_stream(receiver, max, framing),
+        None => receiver,
+    };
+
     let stream = ReceiverStream::new(receiver);
     let stream_body = StreamBody::new(stream);
 
     Ok(Response::builder()
         .status(StatusCode::CREATED)
-        .header(header::CONTENT_TYPE, "text/event-stream")
+        .header(header::CONTENT_TYPE, framing.content_type())
         .header(header::CACHE_CONTROL, "no-cache")
         .header(header::CONNECTION, "keep-alive")
         .body(stream_body.boxed())?)
 }
 
+#[derive(Serialize)]
+struct Imported {
+    entries: usize,
+}
+
+async fn post_chat_import(req: Request<Incoming>) -> ResponseResult {
+    let append = match query_param(&req, "mode").as_deref() {
+        None | Some("replace") => false,
+        Some("append") => true,
+        Some(mode) => {
+            return Ok(problem(
+                StatusCode::BAD_REQUEST,
+                format!("Unknown mode '{}'; expected 'replace' or 'append'", mode),
+            ));
+        }
+    };
+
+    let body = req.collect().await?.to_bytes();
+    let contents = match serde_json::from_slice::<Vec<Content>>(&body) {
+        Ok(contents) => contents,
+        Err(e) => return Ok(invalid_body(&body, e, "array of Content")),
+    };
+
+    let entries = match chat::import_chat(contents, append).await {
+        Ok(entries) => entries,
+        Err(errors) => {
+            let detail = format!("History has {} invalid entries", errors.len());
+            let mut extensions = serde_json::Map::new();
+            extensions.insert("errors".to_string(), serde_json::to_value(errors)?);
+
+            return Ok(problem_with(StatusCode::BAD_REQUEST, detail, extensions));
+        }
+    };
+
+    let json = serde_json::to_string(&Imported { entries })?;
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Full::from(Bytes::from(json)).boxed())
+        .unwrap())
+}
+
+/// Where a request goes, besides static files
+#[derive(Clone, Copy)]
+enum Route {
+    GetChat,
+    GetChatReplay,
+    PostChat,
+    DeleteChat,
+    PostChatImport,
+    PostChatApprove,
+    PostChatChoose,
+    PostChatFeedback,
+    GetCapabilities,
+    GetVersion,
+    GetToolResult,
+    DeleteChatMessage,
+    PostFsUndo,
+    PostAdminShutdown,
+    PostAdminReload,
+}
+
+/// Every route by method and path. A path ending in a `{parameter}` matches whatever follows
+/// its prefix, so those come after the paths they'd otherwise shadow.
+const ROUTES: [(Method, &str, Route); 15] = [
+    (Method::GET, "/chat", Route::GetChat),
+    (Method::GET, "/chat/replay", Route::GetChatReplay),
+    (Method::POST, "/chat", Route::PostChat),
+    (Method::DELETE, "/chat", Route::DeleteChat),
+    (Method::POST, "/chat/import", Route::PostChatImport),
+    (Method::POST, "/chat/approve", Route::PostChatApprove),
+    (Method::POST, "/chat/choose", Route::PostChatChoose),
+    (Method::POST, "/chat/feedback", Route::PostChatFeedback),
+    (Method::GET, "/capabilities", Route::GetCapabilities),
+    (Method::GET, "/version", Route::GetVersion),
+    (Method::POST, "/fs/undo", Route::PostFsUndo),
+    (Method::POST, "/admin/shutdown", Route::PostAdminShutdown),
+    (Method::POST, "/admin/reload", Route::PostAdminReload),
+    (Method::GET, "/chat/tool-result/{id}", Route::GetToolResult),
+    (Method::DELETE, "/chat/{index}", Route::DeleteChatMessage),
+];
+
+/// The route of `method` and `path`, with what matched its parameter if it has one
+fn find_route<'a>(method: &Method, path: &'a str) -> Option<(Route, &'a str)> {
+    ROUTES
+        .iter()
+        .filter(|(m, ..)| m == method)
+        .find_map(|(_, pattern, route)| match pattern.find('{') {
+            Some(at) => path.strip_prefix(&pattern[..at]).map(|param| (*route, param)),
+            None => (path == *pattern).then_some((*route, "")),
+        })
+}
+
+/// Body of the problem for an unknown GET path, kept for the base path it lists routes under,
+/// which changes only on reload
+static NOT_FOUND_BODY: std::sync::Mutex<Option<(String, Bytes)>> = std::sync::Mutex::new(None);
+
+/// Problem for an unknown GET path, listing what can be got under `base_path`
+fn get_not_found(base_path: &str, files: &HashMap<&'static str, (&'static str, Bytes)>) -> Response<BoxBody<Bytes, Infallible>> {
+    let mut cached = NOT_FOUND_BODY.lock().unwrap();
+    let body = match &*cached {
+        Some((path, body)) if path == base_path => body.clone(),
+        _ => {
+            let mut files: Vec<&str> = files.keys().copied().collect();
+            files.sort();
+
+            let routes: Vec<String> = std::iter::once("/")
+                .chain(ROUTES.iter().filter(|(m, ..)| *m == Method::GET).map(|(_, path, _)| *path))
+                .chain(files)
+                .map(|path| format!("{}{}", base_path, path))
+                .collect();
+
+            let mut extensions = serde_json::Map::new();
+            extensions.insert("routes".to_string(), routes.into());
+            let body = problem_json(StatusCode::NOT_FOUND, "No resource at this path", extensions);
+            cached.insert((base_path.to_string(), body)).1.clone()
+        }
+    };
+
+    problem_response(StatusCode::NOT_FOUND, body)
+}
+
 macro_rules! static_file {
-    ($name:expr, $mime:expr) => {
+    ($name:expr) => {
         (
             $name,
             (
-                $mime,
+                mime::from_path($name).unwrap().name,
                 Bytes::from_static(include_bytes!(concat!("www", $name))),
             ),
         )
@@ -90,44 +956,243 @@ macro_rules! static_file {
 
 async fn handle_request(req: Request<Incoming>) -> ResponseResult {
     let files: HashMap<&'static str, (&'static str, Bytes)> = HashMap::from([
-        static_file!("/index.html", "text/html"),
-        static_file!("/main.js", "text/javascript"),
-        static_file!("/sse.js", "text/javascript"),
-        static_file!("/style.css", "text/css"),
+        static_file!("/index.html"),
+        static_file!("/main.js"),
+        static_file!("/sse.js"),
+        static_file!("/style.css"),
     ]);
 
-    let path = match req.uri().path() {
+    let config = CONFIG.get().unwrap();
+    let base_path = config.base_path.as_str();
+    let Some(path) = req.uri().path().strip_prefix(base_path) else {
+        return Ok(problem(
+            StatusCode::NOT_FOUND,
+            format!("Not under base path '{}'", base_path),
+        ));
+    };
+
+    // Static files use relative URLs, so the page must be loaded from a path ending in '/'
+    let path = match path {
+        "" => {
+            return Ok(Response::builder()
+                .status(StatusCode::PERMANENT_REDIRECT)
+                .header(header::LOCATION, format!("{}/", base_path))
+                .body(Full::new(Bytes::new()).boxed())?);
+        }
         "/" => "/index.html",
-        v => v,
+        v if v.starts_with('/') => v,
+        v => {
+            return Ok(problem(
+                StatusCode::NOT_FOUND,
+                format!("Not under base path '{}': '{}{}'", base_path, base_path, v),
+            ));
+        }
     };
 
-    match (req.method(), path) {
-        (&Method::GET, "/chat") => get_chat().await,
-        (&Method::POST, "/chat") => post_chat(req).await,
+    let Some((route, param)) = find_route(req.method(), path) else {
+        if req.method() != Method::GET {
+            return Ok(problem(
+                StatusCode::NOT_FOUND,
+                format!("No route for {} '{}'", req.method(), path),
+            ));
+        }
+
+        let Some((mime, b)) = files.get(path) else {
+            return Ok(get_not_found(base_path, &files));
+        };
 
-        (&Method::GET, p) => {
-            let Some((mime, b)) = files.get(p) else {
-                return Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(Full::new(Bytes::new()).boxed())?);
-            };
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, mime.to_string())
+            .body(Full::new(b.clone()).boxed())?);
+    };
 
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header(header::CONTENT_TYPE, mime.to_string())
-                .body(Full::new(b.clone()).boxed())?)
+    match route {
+        Route::GetChat => get_chat(req).await,
+        Route::GetChatReplay => get_chat_replay().await,
+        Route::PostChat => post_chat(req).await,
+        Route::DeleteChat => delete_chat().await,
+        Route::PostChatImport => post_chat_import(req).await,
+        Route::PostChatApprove => post_chat_approve(req).await,
+        Route::PostChatChoose => post_chat_choose(req).await,
+        Route::PostChatFeedback => post_chat_feedback(req).await,
+        Route::GetCapabilities => get_capabilities().await,
+        Route::GetVersion => get_version().await,
+        Route::GetToolResult => get_tool_result(param).await,
+        Route::DeleteChatMessage => delete_chat_message(param).await,
+        Route::PostFsUndo => post_fs_undo().await,
+        Route::PostAdminShutdown if config.admin_token.is_some() => {
+            let token = config.admin_token.as_deref().unwrap();
+            post_admin_shutdown(req, token).await
+        }
+        Route::PostAdminReload if config.admin_token.is_some() => {
+            let token = config.admin_token.as_deref().unwrap();
+            post_admin_reload(req, token).await
         }
+        // Admin routes don't exist without a token to guard them
+        Route::PostAdminShutdown | Route::PostAdminReload => Ok(problem(
+            StatusCode::NOT_FOUND,
+            format!("No route for {} '{}'", req.method(), path),
+        )),
+    }
+}
 
-        _ => Ok(Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(Full::new(Bytes::from_static(b"Not Found")).boxed())?),
+/// Whether a connection error is just the client going away, which is routine for browsers
+fn is_disconnect(err: &hyper::Error) -> bool {
+    if err.is_incomplete_message() || err.is_canceled() || err.is_closed() || err.is_timeout() {
+        return true;
     }
+
+    let mut source = err.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<std::io::Error>() {
+            return matches!(
+                e.kind(),
+                std::io::ErrorKind::ConnectionReset
+                    | std::io::ErrorKind::ConnectionAborted
+                    | std::io::ErrorKind::BrokenPipe
+                    | std::io::ErrorKind::UnexpectedEof
+            );
+        }
+        source = e.source();
+    }
+
+    false
+}
+
+/// A system instruction naming each of `declarations` with its description
+fn tool_banner(declarations: &[google_ai_rs::proto::FunctionDeclaration]) -> google_ai_rs::Content {
+    let mut banner = "You can call these tools; use them whenever they help answer the user:\n".to_string();
+    for declaration in declarations {
+        let description = declaration.description.split_whitespace().collect::<Vec<_>>().join(" ");
+        banner.push_str(&format!("- {}: {}\n", declaration.name, description));
+    }
+
+    Content::system(vec![Part::new(Data::from(banner))]).into()
+}
+
+/// Where connections come from
+enum Listener {
+    Tcp(TcpListener),
+    /// Bound at the path, which is removed again on shutdown
+    Unix(UnixListener, PathBuf),
+}
+
+enum Stream {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Listener {
+    /// Listens on `YAS_UNIX_SOCKET` if set, on TCP port 8080 otherwise
+    async fn bind(config: &Config) -> std::io::Result<Listener> {
+        let Some(path) = &config.unix_socket else {
+            let addr: SocketAddr = "0.0.0.0:8080".parse().unwrap();
+            return Ok(Listener::Tcp(TcpListener::bind(addr).await?));
+        };
+
+        // A socket left by a run that didn't shut down cleanly would fail the bind
+        if fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            fs::remove_file(path)?;
+        }
+        Ok(Listener::Unix(UnixListener::bind(path)?, path.clone()))
+    }
+
+    async fn accept(&self) -> std::io::Result<Stream> {
+        match self {
+            Listener::Tcp(listener) => Ok(Stream::Tcp(listener.accept().await?.0)),
+            Listener::Unix(listener, _) => Ok(Stream::Unix(listener.accept().await?.0)),
+        }
+    }
+
+    fn close(self) {
+        if let Listener::Unix(listener, path) = self {
+            drop(listener);
+            if let Err(e) = fs::remove_file(&path) {
+                eprintln!("warn: cannot remove socket '{}': {}", path.display(), e);
+            }
+        }
+    }
+}
+
+fn serve_connection<S>(stream: S, graceful: &GracefulShutdown)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let io = TokioIo::new(stream);
+
+    // Only waits for request headers are timed, so streaming responses run as long as they need
+    let conn = http1::Builder::new()
+        .timer(TokioTimer::new())
+        .header_read_timeout(CONFIG.get().unwrap().conn_timeout)
+        .serve_connection(io, service_fn(handle_request));
+    let conn = graceful.watch(conn);
+
+    tokio::task::spawn(async move {
+        match conn.await {
+            Err(err) if is_disconnect(&err) => {
+                if CONFIG.get().unwrap().debug {
+                    eprintln!("debug: client disconnected: {}", err);
+                }
+            }
+            Err(err) => eprintln!("warn: error serving connection: {:?}", err),
+            Ok(()) => {}
+        }
+    });
+}
+
+fn new_model(config: &Config, name: &str) -> GenerativeModel<'static> {
+    let mut model = GenerativeModel::new(CLIENT.get().unwrap(), name);
+
+    let function_declarations = tools::declarations(config);
+    if config.tool_banner && !function_declarations.is_empty() {
+        model.system_instruction = Some(tool_banner(&function_declarations));
+    }
+    if config.candidate_count > 1 {
+        model.generation_config = Some(GenerationConfig {
+            candidate_count: Some(config.candidate_count),
+            ..GenerationConfig::default()
+        });
+    }
+    if !function_declarations.is_empty() {
+        model.tools = Some(vec![Tool {
+            function_declarations,
+            ..Tool::default()
+        }]);
+    }
+    model
+}
+
+/// Builds the default and allowed models for `config`, replacing those new requests use
+fn load_models(config: &Config) {
+    let others = model_names()
+        .into_iter()
+        .skip(1)
+        .map(|name| {
+            let model = Arc::new(new_model(config, &name));
+            (name, model)
+        })
+        .collect();
+
+    MODEL.replace(new_model(config, DEFAULT_MODEL));
+    MODELS.replace(others);
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
+    STARTUP_ENV
+        .set(std::env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?))).collect())
+        .unwrap();
     dotenv().ok();
 
+    CONFIG.replace(Config::from_env()?);
+    tools::load_command_tools(&CONFIG.get().unwrap())?;
+
+    if CONFIG.get().unwrap().mcp {
+        mcp::serve_stdio(CONFIG.get().unwrap()).await?;
+        return Ok(());
+    }
+
     let Some(api_key) = var_os("GEMINI_API_KEY") else {
         panic!("variable GEMINI_API_KEY not set");
     };
@@ -138,29 +1203,116 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let client = Client::new(api_key.into()).await?;
     CLIENT.set(client).unwrap();
 
-    let mut model = GenerativeModel::new(CLIENT.get().unwrap(), "gemini-2.5-pro");
-
-    model.tools = Some(vec![Tool {
-        function_declarations: vec![search_fs_decl(), read_fs_decl()],
-        ..Tool::default()
-    }]);
+    load_models(&CONFIG.get().unwrap());
 
-    MODEL.set(model).unwrap();
+    let listener = Listener::bind(&CONFIG.get().unwrap()).await?;
 
-    let addr: SocketAddr = "0.0.0.0:8080".parse()?;
-    let listener = TcpListener::bind(addr).await?;
+    let graceful = GracefulShutdown::new();
+    let mut sigterm = signal(SignalKind::terminate())?;
 
     loop {
-        let (stream, _) = listener.accept().await?;
-        let io = TokioIo::new(stream);
-
-        tokio::task::spawn(async move {
-            if let Err(err) = http1::Builder::new()
-                .serve_connection(io, service_fn(handle_request))
-                .await
-            {
-                eprintln!("error serving connection: {:?}", err);
-            }
-        });
+        let stream = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = sigterm.recv() => break,
+            _ = tokio::signal::ctrl_c() => break,
+            _ = SHUTDOWN.notified() => break,
+        };
+        match stream {
+            Stream::Tcp(stream) => serve_connection(stream, &graceful),
+            Stream::Unix(stream) => serve_connection(stream, &graceful),
+        }
+    }
+
+    eprintln!("shutting down");
+    listener.close();
+
+    tokio::select! {
+        _ = graceful.shutdown() => {}
+        _ = tokio::time::sleep(SHUTDOWN_TIMEOUT) => {
+            eprintln!("timed out waiting for connections to close");
+        }
+    }
+
+    chat::save_history().await;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn routes_take_their_parameter() {
+        assert!(matches!(find_route(&Method::DELETE, "/chat"), Some((Route::DeleteChat, ""))));
+        assert!(matches!(find_route(&Method::DELETE, "/chat/3"), Some((Route::DeleteChatMessage, "3"))));
+        assert!(matches!(
+            find_route(&Method::GET, "/chat/tool-result/call_1_0"),
+            Some((Route::GetToolResult, "call_1_0"))
+        ));
+        assert!(find_route(&Method::GET, "/chat/3").is_none());
+    }
+
+    #[test]
+    fn query_params_are_found_in_any_order_and_decoded() {
+        let req = |uri: &str| Request::builder().uri(uri).body(()).unwrap();
+        assert_eq!(query_param(&req("/chat/import?mode=append&x=1"), "mode").as_deref(), Some("append"));
+        assert_eq!(query_param(&req("/chat/import?x=1&mode=append&mode=replace"), "mode").as_deref(), Some("append"));
+        assert_eq!(query_param(&req("/chat/import?m%6Fde=a+b%2Cc"), "mode").as_deref(), Some("a b,c"));
+        assert_eq!(query_param(&req("/chat/import?mode"), "mode").as_deref(), Some(""));
+        assert_eq!(query_param(&req("/chat/import?modes=append"), "mode"), None);
+        assert_eq!(query_param(&req("/chat/import"), "mode"), None);
+    }
+
+    #[tokio::test]
+    async fn chat_posted_while_busy_is_refused_when_configured() {
+        let config = Config::from_vars(&|name| (name == "YAS_REJECT_BUSY").then(|| "1".to_string())).unwrap();
+        let post = || {
+            let chat = r#"{"role": "user", "parts": [{"type": "text", "text": "Hi"}]}"#;
+            Request::new(Full::new(Bytes::from(chat)))
+        };
+
+        let _running = Generation::wait().await;
+        let Err(response) = accept_chat(post(), &config).await else {
+            panic!("chat accepted while a generation is running");
+        };
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        let waiting = Config::from_vars(&|_| None).unwrap();
+        assert!(matches!(accept_chat(post(), &waiting).await, Ok((_, None))));
+    }
+
+    #[tokio::test]
+    async fn not_found_lists_get_routes_under_base_path() {
+        let files = HashMap::from([static_file!("/index.html")]);
+        let body = get_not_found("/yas", &files).into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(body["status"], 404);
+        assert_eq!(
+            body["routes"],
+            serde_json::json!([
+                "/yas/",
+                "/yas/chat",
+                "/yas/chat/replay",
+                "/yas/capabilities",
+                "/yas/version",
+                "/yas/chat/tool-result/{id}",
+                "/yas/index.html",
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn not_found_body_is_built_anew_for_another_base_path() {
+        let files = &HashMap::from([static_file!("/index.html")]);
+        let body = |base_path| async move {
+            let body = get_not_found(base_path, files).into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap()["routes"][0].clone()
+        };
+
+        assert_eq!(body("/a").await, "/a/");
+        assert_eq!(body("/a").await, "/a/");
+        assert_eq!(body("").await, "/");
     }
 }
@@ -0,0 +1,217 @@
+//! Serves the tool registry over the Model Context Protocol (JSON-RPC 2.0 on stdio)
+
+use crate::config::Config;
+use crate::defs;
+use crate::tools;
+use crate::tools::{CommandTools, Progress};
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+use tokio::io::{stdin, stdout, AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+const PROTOCOL_VERSION: &str = "2024-11-05";
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+fn schema_to_json(schema: &Schema) -> Value {
+    let mut v = Map::new();
+
+    let r#type = google_ai_rs::proto::Type::try_from(schema.r#type)
+        .unwrap_or(google_ai_rs::proto::Type::Unspecified);
+    let r#type = match r#type {
+        google_ai_rs::proto::Type::Unspecified => None,
+        t => Some(t.as_str_name().to_ascii_lowercase()),
+    };
+
+    if let Some(t) = r#type {
+        if schema.nullable {
+            v.insert("type".to_string(), json!([t, "null"]));
+        } else {
+            v.insert("type".to_string(), json!(t));
+        }
+    }
+    if !schema.description.is_empty() {
+        v.insert("description".to_string(), json!(schema.description));
+    }
+    if !schema.r#enum.is_empty() {
+        v.insert("enum".to_string(), json!(schema.r#enum));
+    }
+    if let Some(items) = &schema.items {
+        v.insert("items".to_string(), schema_to_json(items));
+    }
+    if !schema.properties.is_empty() {
+        let properties: Map<String, Value> = schema
+            .properties
+            .iter()
+            .map(|(k, v)| (k.clone(), schema_to_json(v)))
+            .collect();
+        v.insert("properties".to_string(), Value::Object(properties));
+    }
+    if !schema.required.is_empty() {
+        v.insert("required".to_string(), json!(schema.required));
+    }
+
+    Value::Object(v)
+}
+
+fn decl_to_tool(decl: FunctionDeclaration) -> Value {
+    let input_schema = decl
+        .parameters
+        .as_ref()
+        .map(schema_to_json)
+        .unwrap_or_else(|| json!({ "type": "object" }));
+
+    json!({
+        "name": decl.name,
+        "description": decl.description.trim(),
+        "inputSchema": input_schema,
+    })
+}
+
+fn json_to_value(v: Value) -> prost_types::Value {
+    let kind = match v {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(b) => Kind::BoolValue(b),
+        Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        Value::String(s) => Kind::StringValue(s),
+        Value::Array(a) => Kind::ListValue(prost_types::ListValue {
+            values: a.into_iter().map(json_to_value).collect(),
+        }),
+        Value::Object(o) => Kind::StructValue(json_to_struct(o)),
+    };
+
+    prost_types::Value { kind: Some(kind) }
+}
+
+fn json_to_struct(o: Map<String, Value>) -> prost_types::Struct {
+    prost_types::Struct {
+        fields: o.into_iter().map(|(k, v)| (k, json_to_value(v))).collect(),
+    }
+}
+
+fn call_tool(config: &Config, params: &Value) -> Result<Value, (i64, String)> {
+    let Some(name) = params.get("name").and_then(Value::as_str) else {
+        return Err((INVALID_PARAMS, "Missing tool name".to_string()));
+    };
+    let Some(handler) = tools::handler(config, &CommandTools::current(), name) else {
+        return Err((INVALID_PARAMS, format!("Unknown tool '{}'", name)));
+    };
+
+    let args = match params.get("arguments") {
+        Some(Value::Object(o)) => Some(json_to_struct(o.clone())),
+        None | Some(Value::Null) => None,
+        Some(_) => return Err((INVALID_PARAMS, "Tool arguments must be an object".to_string())),
+    };
+
+    let call = FunctionCall {
+        id: String::new(),
+        name: name.to_string(),
+        args,
+    };
+    let resp = handler.handle(call, &Progress::none());
+
+    let mut response = resp.response.map(defs::Struct::from);
+    if let Some(response) = &mut response {
+        response.fields.remove(tools::DISPLAY_FIELD);
+    }
+    let is_error = response
+        .as_ref()
+        .is_some_and(|r| r.fields.contains_key("error"));
+    let text = serde_json::to_string(&response).unwrap();
+
+    Ok(json!({
+        "content": [{ "type": "text", "text": text }],
+        "isError": is_error,
+    }))
+}
+
+fn dispatch(config: &Config, method: &str, params: &Value) -> Result<Value, (i64, String)> {
+    match method {
+        "initialize" => Ok(json!({
+            "protocolVersion": params
+                .get("protocolVersion")
+                .and_then(Value::as_str)
+                .unwrap_or(PROTOCOL_VERSION),
+            "capabilities": { "tools": {} },
+            "serverInfo": {
+                "name": env!("CARGO_PKG_NAME"),
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        })),
+        "ping" => Ok(json!({})),
+        "tools/list" => Ok(json!({
+            "tools": tools::declarations(config)
+                .into_iter()
+                .map(decl_to_tool)
+                .collect::<Vec<_>>(),
+        })),
+        "tools/call" => call_tool(config, params),
+        _ => Err((METHOD_NOT_FOUND, format!("Method '{}' not found", method))),
+    }
+}
+
+/// Handles one JSON-RPC message; `None` for notifications, which get no reply
+fn handle_message(config: &Config, line: &str) -> Option<Value> {
+    let message: Value = match serde_json::from_str(line) {
+        Ok(v) => v,
+        Err(e) => {
+            return Some(json!({
+                "jsonrpc": "2.0",
+                "id": null,
+                "error": { "code": PARSE_ERROR, "message": e.to_string() },
+            }));
+        }
+    };
+
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(Value::as_str) else {
+        return Some(json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": INVALID_REQUEST, "message": "Missing method" },
+        }));
+    };
+    let params = message.get("params").cloned().unwrap_or(Value::Null);
+
+    let result = dispatch(config, method, &params);
+    let id = id?;
+
+    Some(match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err((code, message)) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": code, "message": message },
+        }),
+    })
+}
+
+pub async fn serve_stdio(config: Arc<Config>) -> std::io::Result<()> {
+    let mut lines = BufReader::new(stdin()).lines();
+    let mut stdout = stdout();
+
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        // Tool calls do blocking filesystem work
+        let config = config.clone();
+        let reply = tokio::task::spawn_blocking(move || handle_message(&config, &line)).await?;
+        let Some(reply) = reply else {
+            continue;
+        };
+
+        let mut reply = serde_json::to_vec(&reply).unwrap();
+        reply.push(b'\n');
+        stdout.write_all(&reply).await?;
+        stdout.flush().await?;
+    }
+
+    Ok(())
+}
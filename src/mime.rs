@@ -0,0 +1,255 @@
+use std::path::Path;
+
+pub struct Mime {
+    pub name: &'static str,
+    /// Content is UTF-8 (or ASCII) text
+    pub is_text: bool,
+}
+
+/// (extension, MIME type, is text)
+const TABLE: &[(&str, &str, bool)] = &[
+    // text
+    ("txt", "text/plain", true),
+    ("md", "text/markdown", true),
+    ("html", "text/html", true),
+    ("htm", "text/html", true),
+    ("css", "text/css", true),
+    ("js", "text/javascript", true),
+    ("mjs", "text/javascript", true),
+    ("csv", "text/csv", true),
+    ("xml", "application/xml", true),
+    ("json", "application/json", true),
+    ("toml", "application/toml", true),
+    ("yaml", "application/yaml", true),
+    ("yml", "application/yaml", true),
+    ("svg", "image/svg+xml", true),
+    // binary
+    ("png", "image/png", false),
+    ("jpg", "image/jpeg", false),
+    ("jpeg", "image/jpeg", false),
+    ("gif", "image/gif", false),
+    ("webp", "image/webp", false),
+    ("ico", "image/vnd.microsoft.icon", false),
+    ("bmp", "image/bmp", false),
+    ("pdf", "application/pdf", false),
+    ("zip", "application/zip", false),
+    ("gz", "application/gzip", false),
+    ("tar", "application/x-tar", false),
+    ("xz", "application/x-xz", false),
+    ("7z", "application/x-7z-compressed", false),
+    ("mp3", "audio/mpeg", false),
+    ("wav", "audio/wav", false),
+    ("mp4", "video/mp4", false),
+    ("webm", "video/webm", false),
+    ("woff", "font/woff", false),
+    ("woff2", "font/woff2", false),
+    ("ttf", "font/ttf", false),
+    ("wasm", "application/wasm", false),
+    ("exe", "application/vnd.microsoft.portable-executable", false),
+    ("so", "application/octet-stream", false),
+    ("o", "application/octet-stream", false),
+    ("a", "application/octet-stream", false),
+    ("rlib", "application/octet-stream", false),
+    ("class", "application/java-vm", false),
+];
+
+/// Classifies a path by its extension; `None` if the extension is unknown
+pub fn from_path(path: impl AsRef<Path>) -> Option<Mime> {
+    let ext = path.as_ref().extension()?.to_str()?.to_ascii_lowercase();
+
+    TABLE
+        .iter()
+        .find(|(e, _, _)| *e == ext)
+        .map(|&(_, name, is_text)| Mime { name, is_text })
+}
+
+struct Signature {
+    offset: usize,
+    magic: &'static [u8],
+    /// Types of data starting this way, the first being the plain format; `*` ends a prefix
+    types: &'static [&'static str],
+    /// What data starting this way is, for people
+    description: &'static str,
+    /// Whether every file of the first type starts this way, so one that doesn't is mislabeled
+    strict: bool,
+}
+
+const fn signature(
+    offset: usize,
+    magic: &'static [u8],
+    types: &'static [&'static str],
+    description: &'static str,
+    strict: bool,
+) -> Signature {
+    Signature { offset, magic, types, description, strict }
+}
+
+/// Formats recognizable by their first bytes
+const SIGNATURES: &[Signature] = &[
+    signature(0, b"\x89PNG\r\n\x1a\n", &["image/png"], "PNG image", true),
+    signature(0, b"\xff\xd8\xff", &["image/jpeg"], "JPEG image", true),
+    signature(0, b"GIF87a", &["image/gif"], "GIF image", true),
+    signature(0, b"GIF89a", &["image/gif"], "GIF image", true),
+    signature(8, b"WEBP", &["image/webp"], "WebP image", true),
+    signature(0, b"%PDF-", &["application/pdf"], "PDF document", true),
+    signature(
+        0,
+        b"PK\x03\x04",
+        &[
+            "application/zip",
+            "application/epub+zip",
+            "application/java-archive",
+            "application/vnd.openxmlformats-officedocument.*",
+            "application/vnd.oasis.opendocument.*",
+        ],
+        "Zip archive, or a format built on one (JAR, EPUB, Office document)",
+        false,
+    ),
+    signature(0, b"\x1f\x8b", &["application/gzip"], "gzip compressed data", false),
+    signature(0, b"\xfd7zXZ\x00", &["application/x-xz"], "XZ compressed data", false),
+    signature(0, b"7z\xbc\xaf\x27\x1c", &["application/x-7z-compressed"], "7-Zip archive", false),
+    signature(0, b"ID3", &["audio/mpeg"], "MP3 audio", false),
+    signature(8, b"WAVE", &["audio/wav"], "WAVE audio", false),
+    signature(
+        4,
+        b"ftyp",
+        &[
+            "video/mp4",
+            "audio/mp4",
+            "video/quicktime",
+            "video/3gpp",
+            "image/heic",
+            "image/heif",
+            "image/avif",
+        ],
+        "ISO media (MP4, QuickTime, HEIF or AVIF)",
+        false,
+    ),
+    signature(
+        0,
+        b"\x1a\x45\xdf\xa3",
+        &["video/webm", "audio/webm", "video/x-matroska"],
+        "Matroska or WebM media",
+        false,
+    ),
+    signature(0, b"wOFF", &["font/woff"], "WOFF font", false),
+    signature(0, b"wOF2", &["font/woff2"], "WOFF2 font", false),
+    signature(0, b"\x00asm", &["application/wasm"], "WebAssembly module", false),
+    signature(0, b"\x7fELF", &["application/x-executable"], "ELF executable or library", false),
+];
+
+/// Other names clients use for types in `SIGNATURES`
+const ALIASES: &[(&str, &str)] = &[
+    ("image/jpg", "image/jpeg"),
+    ("audio/mp3", "audio/mpeg"),
+    ("audio/x-wav", "audio/wav"),
+    ("audio/wave", "audio/wav"),
+    ("audio/m4a", "audio/mp4"),
+    ("audio/x-m4a", "audio/mp4"),
+    ("application/x-gzip", "application/gzip"),
+    ("application/x-zip-compressed", "application/zip"),
+];
+
+/// `mime_type` lowercased, without parameters and under its usual name
+fn normalize(mime_type: &str) -> String {
+    let name = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, name)| name.to_string())
+}
+
+impl Signature {
+    fn matches(&self, data: &[u8]) -> bool {
+        data.get(self.offset..self.offset + self.magic.len()) == Some(self.magic)
+    }
+
+    fn allows(&self, mime_type: &str) -> bool {
+        self.types.iter().any(|t| match t.strip_suffix('*') {
+            Some(prefix) => mime_type.starts_with(prefix),
+            None => *t == mime_type,
+        })
+    }
+}
+
+/// Why `data` can't be what `mime_type` claims, if its first bytes say otherwise or it
+/// claims nothing.
+///
+/// Types without a signature pass unless the data has one of another type.
+pub fn check_declared(mime_type: &str, data: &[u8]) -> Result<(), String> {
+    let declared = normalize(mime_type);
+    if declared.is_empty() {
+        return Err(match sniff(data) {
+            Some(sniffed) => format!("Data has no declared type; it looks like '{}'", sniffed.name),
+            None => "Data has no declared type".to_string(),
+        });
+    }
+
+    match SIGNATURES.iter().find(|signature| signature.matches(data)) {
+        Some(signature) if !signature.allows(&declared) => Err(format!(
+            "Data declared as '{}' looks like '{}'",
+            mime_type, signature.types[0]
+        )),
+        None if SIGNATURES.iter().any(|s| s.strict && s.types[0] == declared) => Err(format!(
+            "Data declared as '{}' doesn't look like it",
+            mime_type
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// What the first bytes of some data say it is
+pub struct Sniffed {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+/// Type of data starting with `head`, if it has a known signature
+pub fn sniff(head: &[u8]) -> Option<Sniffed> {
+    SIGNATURES
+        .iter()
+        .find(|signature| signature.matches(head))
+        .map(|signature| Sniffed {
+            name: signature.types[0],
+            description: signature.description,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    #[test]
+    fn data_of_the_declared_type_passes() {
+        assert!(check_declared("image/png", PNG).is_ok());
+        assert!(check_declared("IMAGE/PNG; name=a.png", PNG).is_ok());
+        assert!(check_declared("image/jpg", b"\xff\xd8\xff\xe0").is_ok());
+        assert!(check_declared("text/plain", b"hello").is_ok());
+        assert!(check_declared("application/vnd.oasis.opendocument.text", b"PK\x03\x04").is_ok());
+    }
+
+    #[test]
+    fn data_of_another_type_is_refused() {
+        assert!(check_declared("image/jpeg", PNG).is_err());
+        assert!(check_declared("text/plain", PNG).is_err());
+        // Every PNG starts with its signature
+        assert!(check_declared("image/png", b"hello").is_err());
+    }
+
+    #[test]
+    fn data_without_a_declared_type_is_refused() {
+        assert_eq!(
+            check_declared("", PNG),
+            Err("Data has no declared type; it looks like 'image/png'".to_string())
+        );
+        assert!(check_declared(" ; charset=utf-8", b"hello").is_err());
+    }
+}
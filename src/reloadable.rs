@@ -0,0 +1,19 @@
+use std::sync::{Arc, RwLock};
+
+/// Like `OnceLock`, but what it holds can be replaced; readers keep using what they got
+/// until they drop it
+pub struct Reloadable<T>(RwLock<Option<Arc<T>>>);
+
+impl<T> Reloadable<T> {
+    pub const fn new() -> Self {
+        Self(RwLock::new(None))
+    }
+
+    pub fn get(&self) -> Option<Arc<T>> {
+        self.0.read().unwrap().clone()
+    }
+
+    pub fn replace(&self, value: T) {
+        *self.0.write().unwrap() = Some(Arc::new(value));
+    }
+}
@@ -0,0 +1,24 @@
+//! Helpers shared by tests
+
+use crate::config::Config;
+use crate::CONFIG;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Mutex, Once};
+
+/// Sets `CONFIG` to what an empty environment gives, unless already set
+pub fn init_config() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| CONFIG.replace(Config::from_vars(&|_| None).unwrap()));
+}
+
+/// A new, empty directory named after `name`, for one test's files
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("yas-test-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Held by tests writing through the undo log, which is one for the whole process
+pub static UNDO_LOG: Mutex<()> = Mutex::new(());
@@ -0,0 +1,124 @@
+use google_ai_rs::error::{NetError, ServiceError, TonicStatus};
+use google_ai_rs::Error;
+use prost::Message;
+use std::time::Duration;
+use tonic::{Code, Status};
+
+/// `google.rpc.Status`, as gRPC carries it in `grpc-status-details-bin`, down to its details
+#[derive(Clone, PartialEq, Message)]
+struct RpcStatus {
+    #[prost(message, repeated, tag = "3")]
+    details: Vec<prost_types::Any>,
+}
+
+/// `google.rpc.RetryInfo`, one of the details a throttled request may come back with
+#[derive(Clone, PartialEq, Message)]
+struct RetryInfo {
+    #[prost(message, optional, tag = "1")]
+    retry_delay: Option<prost_types::Duration>,
+}
+
+const RETRY_INFO_TYPE: &str = "type.googleapis.com/google.rpc.RetryInfo";
+
+fn status(error: &Error) -> Option<&Status> {
+    match error {
+        Error::Service(ServiceError::ApiError(TonicStatus(status)))
+        | Error::Net(NetError::ServiceUnavailable(TonicStatus(status))) => Some(status),
+        _ => None,
+    }
+}
+
+/// Whether the API turned the request away for quota or rate limits, the gRPC form of `429`
+pub fn is_throttled(error: &Error) -> bool {
+    status(error).is_some_and(|status| status.code() == Code::ResourceExhausted)
+}
+
+fn retry_info(status: &Status) -> Option<Duration> {
+    let details = RpcStatus::decode(status.details()).ok()?.details;
+    let delay = details
+        .into_iter()
+        .filter(|any| any.type_url == RETRY_INFO_TYPE)
+        .find_map(|any| RetryInfo::decode(any.value.as_slice()).ok()?.retry_delay)?;
+
+    Some(Duration::new(delay.seconds.max(0) as u64, delay.nanos.max(0) as u32))
+}
+
+fn retry_after_header(status: &Status) -> Option<Duration> {
+    let seconds = status.metadata().get("retry-after")?.to_str().ok()?;
+    seconds.trim().parse().ok().map(Duration::from_secs)
+}
+
+/// The `retry in 12.3s` the message of a quota error ends with
+fn retry_in_message(status: &Status) -> Option<Duration> {
+    let message = status.message();
+    let at = message.find("retry in ")? + "retry in ".len();
+    let seconds = message[at..].split('s').next()?;
+    Duration::try_from_secs_f64(seconds.parse().ok()?).ok()
+}
+
+/// How long a throttled request asks to be waited on before it's retried, from its
+/// `RetryInfo`, its `retry-after` header or its message, in that order
+pub fn retry_after(error: &Error) -> Option<Duration> {
+    let status = status(error)?;
+    retry_info(status)
+        .or_else(|| retry_after_header(status))
+        .or_else(|| retry_in_message(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tonic::metadata::MetadataMap;
+
+    fn throttled(message: &str, retry_after: Option<&str>) -> Error {
+        let mut metadata = MetadataMap::new();
+        if let Some(retry_after) = retry_after {
+            metadata.insert("retry-after", retry_after.parse().unwrap());
+        }
+        let status = Status::with_metadata(Code::ResourceExhausted, message, metadata);
+        Error::Service(ServiceError::ApiError(TonicStatus(Box::new(status))))
+    }
+
+    #[test]
+    fn retry_after_header_is_seconds() {
+        let error = throttled("Quota exceeded", Some(" 7 "));
+        assert!(is_throttled(&error));
+        assert_eq!(retry_after(&error), Some(Duration::from_secs(7)));
+    }
+
+    #[test]
+    fn missing_retry_after_falls_back_to_the_message() {
+        assert_eq!(
+            retry_after(&throttled("Quota exceeded, retry in 1.5s.", None)),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(retry_after(&throttled("Quota exceeded", None)), None);
+    }
+
+    #[test]
+    fn garbage_retry_after_is_ignored() {
+        assert_eq!(retry_after(&throttled("Quota exceeded", Some("soon"))), None);
+        assert_eq!(retry_after(&throttled("Quota exceeded", Some("-3"))), None);
+        assert_eq!(
+            retry_after(&throttled("Quota exceeded, retry in 2s", Some("Wed, 21 Oct 2015 07:28:00 GMT"))),
+            Some(Duration::from_secs(2))
+        );
+    }
+
+    #[test]
+    fn retry_info_comes_first() {
+        let info = RetryInfo {
+            retry_delay: Some(prost_types::Duration { seconds: 3, nanos: 0 }),
+        };
+        let details = RpcStatus {
+            details: vec![prost_types::Any {
+                type_url: RETRY_INFO_TYPE.to_string(),
+                value: info.encode_to_vec(),
+            }],
+        };
+        let mut status = Status::with_details(Code::ResourceExhausted, "Quota exceeded", details.encode_to_vec().into());
+        status.metadata_mut().insert("retry-after", "9".parse().unwrap());
+        let error = Error::Service(ServiceError::ApiError(TonicStatus(Box::new(status))));
+        assert_eq!(retry_after(&error), Some(Duration::from_secs(3)));
+    }
+}
@@ -0,0 +1,209 @@
+use crate::undo;
+use crate::tools::args::get_string;
+use crate::tools::read_fs::text_style;
+use crate::tools::{error_kind_schema, writable_path, Progress, ToolError};
+use crate::CONFIG;
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::{Struct, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+/// Bytes at the start of a file looked at for its line ending
+const STYLE_SAMPLE_LENGTH: u64 = 64 * 1024;
+
+fn respond_result(size: u64, undoable: bool) -> Struct {
+    Struct {
+        fields: BTreeMap::from([
+            ("size".to_string(), Value::from(size as f64)),
+            ("undoable".to_string(), Value::from(undoable)),
+        ]),
+    }
+}
+
+/// Whether the file at `path` exists and ends its lines with CRLF throughout
+fn uses_crlf(path: &str) -> bool {
+    let mut sample = vec![];
+    let read = File::open(path).and_then(|f| f.take(STYLE_SAMPLE_LENGTH).read_to_end(&mut sample));
+    read.is_ok() && text_style(&String::from_utf8_lossy(&sample)).0 == Some("crlf")
+}
+
+/// `content` with lone LFs turned into CRLF
+fn to_crlf(content: &str) -> String {
+    let mut converted = String::with_capacity(content.len());
+    let mut prev = None;
+    for c in content.chars() {
+        if c == '\n' && prev != Some('\r') {
+            converted.push('\r');
+        }
+        converted.push(c);
+        prev = Some(c);
+    }
+    converted
+}
+
+fn append_fs(path: &str, content: &str) -> Result<(u64, bool), Box<dyn std::error::Error>> {
+    let path = writable_path(&CONFIG.get().unwrap(), path)?;
+    let path = path.to_str().ok_or("Path is not valid UTF-8")?;
+
+    let undoable = undo::snapshot("append_fs", path).map_err(|e| format!("Cannot snapshot for undo: {}", e))?;
+
+    // Text written into a CRLF file keeps it that way, whatever line endings the model used
+    let content = if uses_crlf(path) { to_crlf(content) } else { content.to_string() };
+
+    let mut file = OpenOptions::new().append(true).create(true).open(path)?;
+    file.write_all(content.as_bytes())?;
+    Ok((file.metadata()?.len(), undoable))
+}
+
+pub fn handle_append_fs(call: FunctionCall, _: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "append_fs");
+
+    let Some(args) = call.args.as_ref() else {
+        return FunctionResponse{
+            id: call.id,
+            name: call.name,
+            response: Some(ToolError::InvalidArgs("Argument is none".to_string()).into()),
+        };
+    };
+
+    let (path, content) = match (get_string(args, "path"), get_string(args, "content")) {
+        (Ok(path), Ok(content)) => (path, content),
+        (Err(e), _) | (_, Err(e)) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+
+    let resp = match append_fs(path, content) {
+        Ok((size, undoable)) => respond_result(size, undoable),
+        Err(e) => ToolError::ExecutionFailed(e.to_string()).into()
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+pub fn append_fs_decl() -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: "append_fs".to_string(),
+        description: r#"
+        Append text to the end of a file on user's filesystem.
+        The file is created if it doesn't exist.
+        Prefer this over rewriting a whole file when only adding content.
+        Line breaks are written as CRLF if the file already uses CRLF.
+        The user may confine writes to one directory; paths outside it are refused.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("path".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "Path of file to append to".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("content".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "Text to append".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            required: vec!["path".to_string(), "content".to_string()],
+            ..Schema::default()
+        }),
+        response: Some(Schema{
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("error".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Error during append".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+                ("size".to_string(), Schema{
+                    r#type: 3, /* INTEGER */
+                    description: "(Optional) Total size of file in bytes after append".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("undoable".to_string(), Schema{
+                    r#type: 4, /* BOOLEAN */
+                    description: "(Optional) Whether the user can undo this append".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{init_config, temp_dir, UNDO_LOG};
+    use std::fs;
+
+    fn appended(name: &str, existing: &str) -> String {
+        init_config();
+        let _log = UNDO_LOG.lock().unwrap();
+        let path = temp_dir(name).join("file.txt");
+        fs::write(&path, existing).unwrap();
+
+        append_fs(path.to_str().unwrap(), "third\nfourth\n").unwrap();
+        fs::read_to_string(&path).unwrap()
+    }
+
+    #[test]
+    fn crlf_file_stays_crlf() {
+        let content = appended("append-crlf", "first\r\nsecond\r\n");
+        assert_eq!(content, "first\r\nsecond\r\nthird\r\nfourth\r\n");
+        assert!(content.split_inclusive('\n').all(|line| line.ends_with("\r\n")));
+    }
+
+    #[test]
+    fn lf_file_stays_lf() {
+        let content = appended("append-lf", "first\nsecond\n");
+        assert_eq!(content, "first\nsecond\nthird\nfourth\n");
+        assert!(!content.contains('\r'));
+    }
+
+    #[test]
+    fn undo_stops_at_a_file_too_large_to_snapshot() {
+        init_config();
+        let _log = UNDO_LOG.lock().unwrap();
+        let dir = temp_dir("append-undo-large");
+        let small = dir.join("small.txt");
+        let large = dir.join("large.txt");
+        fs::write(&small, "small\n").unwrap();
+        fs::write(&large, "x".repeat(2 * 1024 * 1024)).unwrap();
+
+        assert!(append_fs(small.to_str().unwrap(), "more\n").unwrap().1);
+        assert!(!append_fs(large.to_str().unwrap(), "more\n").unwrap().1);
+
+        // Undoing the small append would drop the large one with it
+        assert!(matches!(undo::undo(), Err(undo::UndoError::NotUndoable { .. })));
+        assert!(matches!(undo::undo(), Err(undo::UndoError::NotUndoable { .. })));
+        assert_eq!(fs::read_to_string(&small).unwrap(), "small\nmore\n");
+        assert!(fs::read_to_string(&large).unwrap().ends_with("xmore\n"));
+
+        // What comes after can be undone again, up to the barrier
+        assert!(append_fs(small.to_str().unwrap(), "again\n").unwrap().1);
+        assert!(undo::undo().is_ok());
+        assert_eq!(fs::read_to_string(&small).unwrap(), "small\nmore\n");
+        assert!(matches!(undo::undo(), Err(undo::UndoError::NotUndoable { .. })));
+    }
+}
@@ -0,0 +1,44 @@
+use prost_types::value::Kind;
+use prost_types::Struct;
+
+/// Optional boolean argument `name`, `default` if absent or null
+pub fn get_bool(args: &Struct, name: &str, default: bool) -> Result<bool, String> {
+    match args.fields.get(name).and_then(|v| v.kind.as_ref()) {
+        None | Some(Kind::NullValue(_)) => Ok(default),
+        Some(Kind::BoolValue(b)) => Ok(*b),
+        Some(_) => Err(format!("Boolean argument '{}' is not a boolean", name)),
+    }
+}
+
+/// Required string argument `name`
+pub fn get_string<'a>(args: &'a Struct, name: &str) -> Result<&'a str, String> {
+    let Some(value) = args.fields.get(name) else {
+        return Err(format!("Required argument '{}' is missing", name));
+    };
+
+    match &value.kind {
+        Some(Kind::StringValue(s)) => Ok(s),
+        Some(_) => Err(format!("String argument '{}' is not a string", name)),
+        None => Err(format!("Required argument '{}' is null", name)),
+    }
+}
+
+/// Optional string argument `name`, `None` if absent or null
+pub fn get_opt_string<'a>(args: &'a Struct, name: &str) -> Result<Option<&'a str>, String> {
+    match args.fields.get(name).and_then(|v| v.kind.as_ref()) {
+        None | Some(Kind::NullValue(_)) => Ok(None),
+        Some(Kind::StringValue(s)) => Ok(Some(s)),
+        Some(_) => Err(format!("String argument '{}' is not a string", name)),
+    }
+}
+
+/// Optional non-negative integer argument `name`, `None` if absent or null
+pub fn get_opt_u64(args: &Struct, name: &str) -> Result<Option<u64>, String> {
+    match args.fields.get(name).and_then(|v| v.kind.as_ref()) {
+        None | Some(Kind::NullValue(_)) => Ok(None),
+        Some(Kind::NumberValue(n)) if *n >= 0.0 && n.fract() == 0.0 && *n <= u64::MAX as f64 => {
+            Ok(Some(*n as u64))
+        }
+        Some(_) => Err(format!("Integer argument '{}' is not a non-negative integer", name)),
+    }
+}
@@ -0,0 +1,211 @@
+use crate::tools::args::get_string;
+use crate::tools::search_fs::{search_fs, SearchOptions};
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use glob::Pattern;
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use prost_types::{Struct, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Most extensions listed; the rest are summed up in `other`
+const MAX_EXTENSIONS: usize = 50;
+
+#[derive(Default)]
+struct Total {
+    count: u64,
+    bytes: u64,
+}
+
+impl Total {
+    fn into_struct(self) -> Struct {
+        Struct {
+            fields: BTreeMap::from([
+                ("count".to_string(), Value::from(self.count as f64)),
+                ("bytes".to_string(), Value::from(self.bytes as f64)),
+            ]),
+        }
+    }
+}
+
+/// Extension of `path` with its dot, e.g. `.rs`, or an empty string if it has none
+fn extension_of(path: &str) -> String {
+    match Path::new(path).extension() {
+        Some(extension) => format!(".{}", extension.to_string_lossy()),
+        None => String::new(),
+    }
+}
+
+/// Counts and sizes of the files under `root` by extension, largest first. Hidden files
+/// and directories, `.git` among them, are skipped as `search_fs` skips them.
+fn breakdown_fs(root: &str, progress: &Progress) -> Struct {
+    let pattern = format!("{}/**/*", Pattern::escape(root.trim_end_matches('/')));
+    let (entries, errors) = search_fs(&pattern, &SearchOptions::default(), progress);
+
+    let mut by_extension: HashMap<String, Total> = HashMap::new();
+    for entry in entries.into_iter().filter(|entry| entry.is_file()) {
+        let total = by_extension.entry(extension_of(&entry.path)).or_default();
+        total.count += 1;
+        total.bytes += entry.size;
+    }
+
+    let mut extensions: Vec<(String, Total)> = by_extension.into_iter().collect();
+    extensions.sort_by_key(|(extension, total)| (Reverse(total.bytes), extension.clone()));
+
+    let mut other = Total::default();
+    let mut results = vec![];
+    for (i, (extension, total)) in extensions.into_iter().enumerate() {
+        if i >= MAX_EXTENSIONS {
+            other.count += total.count;
+            other.bytes += total.bytes;
+            continue;
+        }
+
+        let mut fields = total.into_struct().fields;
+        fields.insert("extension".to_string(), Value::from(extension));
+        results.push(Value::from(Kind::StructValue(Struct { fields })));
+    }
+
+    let errors: Vec<Value> = errors.into_iter().map(Value::from).collect();
+    let mut fields = BTreeMap::from([
+        ("extensions".to_string(), Value::from(results)),
+        ("errors".to_string(), Value::from(errors)),
+    ]);
+    if other.count > 0 {
+        fields.insert("other".to_string(), Value::from(Kind::StructValue(other.into_struct())));
+    }
+
+    Struct { fields }
+}
+
+pub fn handle_breakdown_fs(call: FunctionCall, progress: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "breakdown_fs");
+
+    let Some(args) = call.args.as_ref() else {
+        return FunctionResponse{
+            id: call.id,
+            name: call.name,
+            response: Some(ToolError::InvalidArgs("Argument is none".to_string()).into()),
+        };
+    };
+
+    let root = match get_string(args, "path") {
+        Ok(root) => root,
+        Err(e) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+
+    let resp = if Path::new(root).is_dir() {
+        breakdown_fs(root, progress)
+    } else {
+        ToolError::InvalidArgs(format!("'{}' is not a directory", root)).into()
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+fn total_properties() -> HashMap<String, Schema> {
+    HashMap::from([
+        ("count".to_string(), Schema{
+            r#type: 3, /* INTEGER */
+            description: "Number of files".to_string(),
+            nullable: false,
+            ..Schema::default()
+        }),
+        ("bytes".to_string(), Schema{
+            r#type: 3, /* INTEGER */
+            description: "Total size of the files in bytes".to_string(),
+            nullable: false,
+            ..Schema::default()
+        }),
+    ])
+}
+
+pub fn breakdown_fs_decl() -> FunctionDeclaration {
+    let mut extension_properties = total_properties();
+    extension_properties.insert("extension".to_string(), Schema {
+        r#type: 1, /* STRING */
+        description: "Extension with its dot (e.g. `.rs`), or empty for files without one".to_string(),
+        nullable: false,
+        ..Schema::default()
+    });
+
+    FunctionDeclaration {
+        name: "breakdown_fs".to_string(),
+        description: r#"
+        Count files and their total size by extension under a directory on user's filesystem, recursively.
+        Useful to see what a project is made of in one call instead of many globs.
+        Hidden files and directories (e.g. `.git`) are skipped.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("path".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "Directory to walk".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            required: vec!["path".to_string()],
+            ..Schema::default()
+        }),
+        response: Some(Schema{
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("extensions".to_string(), Schema{
+                    r#type: 5, /* ARRAY */
+                    description: format!("Totals by extension, largest first; at most {}", MAX_EXTENSIONS),
+                    nullable: false,
+                    items: Some(Box::new(Schema {
+                        r#type: 6, /* OBJECT */
+                        nullable: false,
+                        properties: extension_properties,
+                        ..Schema::default()
+                    })),
+                    ..Schema::default()
+                }),
+                ("other".to_string(), Schema{
+                    r#type: 6, /* OBJECT */
+                    description: "(Optional) Totals of the extensions left out of `extensions`".to_string(),
+                    nullable: false,
+                    properties: total_properties(),
+                    ..Schema::default()
+                }),
+                ("errors".to_string(), Schema{
+                    r#type: 5, /* ARRAY */
+                    description: "Exceptions occurred during the walk".to_string(),
+                    nullable: false,
+                    items: Some(Box::new(Schema {
+                        r#type: 1, /* STRING */
+                        nullable: false,
+                        ..Schema::default()
+                    })),
+                    ..Schema::default()
+                }),
+                ("error".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Why the walk couldn't start".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
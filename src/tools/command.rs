@@ -0,0 +1,561 @@
+//! Tools declared in `YAS_TOOLS_FILE`, each running a fixed command line.
+//!
+//! Commands are executed directly, never through a shell. Every `{name}` placeholder in
+//! `command_template` becomes a required string parameter, and its value is substituted
+//! within that one argument, so values can't add arguments or be interpreted by a shell.
+//!
+//! Output is shown to the user as the command writes it; the model gets it once it ends.
+
+use crate::reloadable::Reloadable;
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use crate::utf8::Utf8Buffer;
+use crate::CONFIG;
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use prost_types::{Struct, Value};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{channel, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+/// Bytes of stdout and of stderr returned to the model
+const MAX_OUTPUT_BYTES: usize = 64 * 1024;
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+static COMMAND_TOOLS: Reloadable<Vec<CommandTool>> = Reloadable::new();
+
+#[derive(Deserialize)]
+struct CommandTool {
+    name: String,
+    description: String,
+    /// Program followed by its arguments, e.g. `["git", "-C", "{repo}", "log"]`
+    command_template: Vec<String>,
+}
+
+/// Piece of a template argument
+enum Segment<'a> {
+    Text(&'a str),
+    Placeholder(&'a str),
+}
+
+/// `arg` split into text and `{placeholder}`s, left to right
+fn segments(arg: &str) -> Vec<Segment<'_>> {
+    let mut segments = vec![];
+    let mut text_start = 0;
+    let mut pos = 0;
+
+    while let Some(open) = arg[pos..].find('{').map(|i| pos + i) {
+        let Some(close) = arg[open + 1..].find('}').map(|i| open + 1 + i) else {
+            break;
+        };
+
+        let name = &arg[open + 1..close];
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            if text_start < open {
+                segments.push(Segment::Text(&arg[text_start..open]));
+            }
+            segments.push(Segment::Placeholder(name));
+            text_start = close + 1;
+            pos = close + 1;
+        } else {
+            pos = open + 1;
+        }
+    }
+    if text_start < arg.len() {
+        segments.push(Segment::Text(&arg[text_start..]));
+    }
+
+    segments
+}
+
+/// Names of `{placeholder}`s in `arg`, in order
+fn placeholders(arg: &str) -> Vec<&str> {
+    segments(arg)
+        .into_iter()
+        .filter_map(|segment| match segment {
+            Segment::Placeholder(name) => Some(name),
+            Segment::Text(_) => None,
+        })
+        .collect()
+}
+
+impl CommandTool {
+    fn parameters(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = vec![];
+        for name in self.command_template.iter().flat_map(|arg| placeholders(arg)) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        names
+    }
+
+    fn decl(&self) -> FunctionDeclaration {
+        let parameters = self.parameters();
+
+        FunctionDeclaration {
+            name: self.name.clone(),
+            description: self.description.clone(),
+            parameters: Some(Schema {
+                r#type: 6, /* OBJECT */
+                nullable: false,
+                properties: parameters
+                    .iter()
+                    .map(|name| {
+                        (name.to_string(), Schema {
+                            r#type: 1, /* STRING */
+                            nullable: false,
+                            ..Schema::default()
+                        })
+                    })
+                    .collect(),
+                required: parameters.iter().map(|name| name.to_string()).collect(),
+                ..Schema::default()
+            }),
+            response: Some(Schema {
+                r#type: 6, /* OBJECT */
+                nullable: false,
+                properties: HashMap::from([
+                    ("error".to_string(), Schema {
+                        r#type: 1, /* STRING */
+                        description: "(Optional) Why the command couldn't be run".to_string(),
+                        nullable: false,
+                        ..Schema::default()
+                    }),
+                    ("error_kind".to_string(), error_kind_schema()),
+                    ("status".to_string(), Schema {
+                        r#type: 3, /* INTEGER */
+                        description: "(Optional) Exit status; absent if killed by a signal or timeout".to_string(),
+                        nullable: false,
+                        ..Schema::default()
+                    }),
+                    ("stdout".to_string(), Schema {
+                        r#type: 1, /* STRING */
+                        nullable: false,
+                        ..Schema::default()
+                    }),
+                    ("stderr".to_string(), Schema {
+                        r#type: 1, /* STRING */
+                        nullable: false,
+                        ..Schema::default()
+                    }),
+                    ("timed_out".to_string(), Schema {
+                        r#type: 4, /* BOOLEAN */
+                        nullable: false,
+                        ..Schema::default()
+                    }),
+                ]),
+                ..Schema::default()
+            }),
+        }
+    }
+
+    fn build_argv(&self, args: Option<&Struct>) -> Result<Vec<String>, String> {
+        let mut argv = vec![];
+
+        for template in &self.command_template {
+            // One pass, so a value is never searched for placeholders itself
+            let mut arg = String::new();
+            for (i, segment) in segments(template).into_iter().enumerate() {
+                let name = match segment {
+                    Segment::Text(text) => {
+                        arg.push_str(text);
+                        continue;
+                    }
+                    Segment::Placeholder(name) => name,
+                };
+                let value = match args.and_then(|args| args.fields.get(name)) {
+                    Some(Value { kind: Some(Kind::StringValue(s)) }) => s,
+                    Some(_) => return Err(format!("String argument '{}' is not a string", name)),
+                    None => return Err(format!("Required argument '{}' is missing", name)),
+                };
+
+                // A value opening an argument could otherwise pass itself off as an option
+                if i == 0 && value.starts_with('-') {
+                    return Err(format!("Argument '{}' may not start with '-'", name));
+                }
+
+                arg.push_str(value);
+            }
+
+            argv.push(arg);
+        }
+
+        Ok(argv)
+    }
+}
+
+/// Loads command tools from `path`, refusing names taken by `reserved` tools; none without a path.
+///
+/// Tools loaded before are replaced only if the new ones are valid.
+pub fn load(path: Option<&Path>, reserved: &[&str]) -> Result<(), String> {
+    let Some(path) = path else {
+        COMMAND_TOOLS.replace(vec![]);
+        return Ok(());
+    };
+
+    let s = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read tools file '{}': {}", path.display(), e))?;
+    let tools: Vec<CommandTool> = serde_json::from_str(&s)
+        .map_err(|e| format!("invalid tools file '{}': {}", path.display(), e))?;
+
+    for (i, tool) in tools.iter().enumerate() {
+        if tool.command_template.is_empty() {
+            return Err(format!("tool '{}' has an empty command_template", tool.name));
+        }
+        if reserved.contains(&tool.name.as_str()) {
+            return Err(format!("tool '{}' conflicts with a built-in tool", tool.name));
+        }
+        if tools[..i].iter().any(|t| t.name == tool.name) {
+            return Err(format!("tool '{}' is declared more than once", tool.name));
+        }
+    }
+
+    COMMAND_TOOLS.replace(tools);
+    Ok(())
+}
+
+/// Command tools as registered at one time, which a generation keeps using through a reload
+#[derive(Clone)]
+pub struct CommandTools(Arc<Vec<CommandTool>>);
+
+impl CommandTools {
+    /// The command tools registered now
+    pub fn current() -> Self {
+        Self(COMMAND_TOOLS.get().unwrap_or_default())
+    }
+
+    pub fn declarations(&self) -> Vec<FunctionDeclaration> {
+        self.0.iter().map(CommandTool::decl).collect()
+    }
+
+    pub fn exists(&self, name: &str) -> bool {
+        self.0.iter().any(|tool| tool.name == name)
+    }
+}
+
+/// Which output of a command a chunk was read from
+#[derive(Clone, Copy)]
+enum Output {
+    Stdout,
+    Stderr,
+}
+
+impl Output {
+    fn name(self) -> &'static str {
+        match self {
+            Output::Stdout => "stdout",
+            Output::Stderr => "stderr",
+        }
+    }
+}
+
+/// Reads `r` to the end, sending each chunk of the first `MAX_OUTPUT_BYTES` to `chunks` as
+/// soon as it's read
+fn read_capped(mut r: impl Read, output: Output, chunks: Sender<(Output, Vec<u8>)>) {
+    let mut read = 0;
+    let mut chunk = [0; 8192];
+    while read < MAX_OUTPUT_BYTES {
+        let want = chunk.len().min(MAX_OUTPUT_BYTES - read);
+        match r.read(&mut chunk[..want]) {
+            Ok(0) => break,
+            Ok(n) => {
+                read += n;
+                let _ = chunks.send((output, chunk[..n].to_vec()));
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => break,
+        }
+    }
+    // Drain the rest so the child never blocks on a full pipe
+    let _ = std::io::copy(&mut r, &mut std::io::sink());
+}
+
+/// Collects output as it comes, and turns it into text for the user to see before the
+/// command ends
+struct OutputStream<'a> {
+    progress: &'a Progress,
+    stdout: Utf8Buffer,
+    stderr: Utf8Buffer,
+    stdout_bytes: Vec<u8>,
+    stderr_bytes: Vec<u8>,
+}
+
+impl<'a> OutputStream<'a> {
+    fn new(progress: &'a Progress) -> Self {
+        Self {
+            progress,
+            stdout: Utf8Buffer::default(),
+            stderr: Utf8Buffer::default(),
+            stdout_bytes: vec![],
+            stderr_bytes: vec![],
+        }
+    }
+
+    fn send(&self, output: Output, text: String) {
+        if !text.is_empty() {
+            self.progress.output(Struct {
+                fields: BTreeMap::from([(output.name().to_string(), Value::from(text))]),
+            });
+        }
+    }
+
+    fn forward(&mut self, (output, bytes): (Output, Vec<u8>)) {
+        let text = match output {
+            Output::Stdout => {
+                self.stdout_bytes.extend_from_slice(&bytes);
+                self.stdout.push(&bytes)
+            }
+            Output::Stderr => {
+                self.stderr_bytes.extend_from_slice(&bytes);
+                self.stderr.push(&bytes)
+            }
+        };
+        self.send(output, text);
+    }
+
+    /// Everything read from stdout and stderr
+    fn finish(mut self) -> (String, String) {
+        let (stdout, stderr) = (self.stdout.finish(), self.stderr.finish());
+        self.send(Output::Stdout, stdout);
+        self.send(Output::Stderr, stderr);
+        (
+            String::from_utf8_lossy(&self.stdout_bytes).into_owned(),
+            String::from_utf8_lossy(&self.stderr_bytes).into_owned(),
+        )
+    }
+}
+
+/// Kills the command and whatever it started, all being in its process group
+fn kill_group(child: &Child) {
+    // SAFETY: kill has no memory effects; a negative pid names the group the child leads
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/// Runs `argv` until it exits and its output is closed, or for `timeout` at most.
+///
+/// Processes it starts may keep its output open after it exits, so on timeout its whole
+/// process group is killed, and readers still waiting on the output are left behind.
+fn run(argv: &[String], timeout: Duration, progress: &Progress) -> Result<Struct, String> {
+    let mut child = Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(|e| format!("Cannot run '{}': {}", argv[0], e))?;
+
+    let (chunks, received) = channel();
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    {
+        let chunks = chunks.clone();
+        std::thread::spawn(move || read_capped(stdout, Output::Stdout, chunks));
+    }
+    std::thread::spawn(move || read_capped(stderr, Output::Stderr, chunks));
+
+    let mut stream = OutputStream::new(progress);
+    let deadline = Instant::now() + timeout;
+    let mut timed_out = false;
+    let mut status = None;
+    let mut closed = false;
+
+    loop {
+        if closed {
+            sleep(POLL_INTERVAL);
+        } else {
+            match received.recv_timeout(POLL_INTERVAL) {
+                Ok(chunk) => stream.forward(chunk),
+                Err(RecvTimeoutError::Timeout) => {}
+                // Both readers are done, the output having been closed
+                Err(RecvTimeoutError::Disconnected) => closed = true,
+            }
+        }
+
+        if status.is_none() {
+            status = child.try_wait().map_err(|e| e.to_string())?;
+        }
+        if status.is_some() && closed {
+            break;
+        }
+
+        if Instant::now() >= deadline {
+            timed_out = true;
+            kill_group(&child);
+            if status.is_none() {
+                let _ = child.wait();
+            }
+            // Output written before the kill; a process that left the group may hold
+            // the pipes open still, and isn't waited for
+            let grace = Instant::now() + POLL_INTERVAL * 5;
+            while let Ok(chunk) = received.recv_timeout(grace.saturating_duration_since(Instant::now())) {
+                stream.forward(chunk);
+            }
+            break;
+        }
+    }
+
+    let (stdout, stderr) = stream.finish();
+
+    let mut fields = BTreeMap::from([
+        ("stdout".to_string(), Value::from(stdout)),
+        ("stderr".to_string(), Value::from(stderr)),
+        ("timed_out".to_string(), Value::from(timed_out)),
+    ]);
+    if let Some(code) = status.and_then(|s| s.code()) {
+        fields.insert("status".to_string(), Value::from(code));
+    }
+
+    Ok(Struct { fields })
+}
+
+pub fn handle_command(tools: &CommandTools, call: FunctionCall, progress: &Progress) -> FunctionResponse {
+    let tool = tools
+        .0
+        .iter()
+        .find(|tool| tool.name == call.name)
+        .expect("handler is only given calls of command tools");
+
+    let resp = tool
+        .build_argv(call.args.as_ref())
+        .map_err(ToolError::InvalidArgs)
+        .and_then(|argv| {
+            run(&argv, CONFIG.get().unwrap().tool_timeout, progress).map_err(ToolError::ExecutionFailed)
+        });
+
+    let resp = resp.unwrap_or_else(Struct::from);
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{init_config, temp_dir};
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn field<'a>(result: &'a Struct, name: &str) -> &'a Option<Kind> {
+        &result.fields[name].kind
+    }
+
+    fn tool(template: &[&str]) -> CommandTool {
+        CommandTool {
+            name: "tool".to_string(),
+            description: String::new(),
+            command_template: argv(template),
+        }
+    }
+
+    fn args(values: &[(&str, &str)]) -> Struct {
+        Struct {
+            fields: values
+                .iter()
+                .map(|(name, value)| (name.to_string(), Value::from(value.to_string())))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_named_braces_alone() {
+        assert_eq!(placeholders("{a}-{b_2}"), vec!["a", "b_2"]);
+        assert_eq!(placeholders("{}{not one}{x"), Vec::<&str>::new());
+        assert_eq!(placeholders("{{a}}"), vec!["a"]);
+        assert_eq!(tool(&["git", "{a}", "--{b}={a}"]).parameters(), vec!["a", "b"]);
+    }
+
+    #[test]
+    fn values_are_substituted_once() {
+        let tool = tool(&["echo", "{a}:{b}", "{b}"]);
+        let argv = tool.build_argv(Some(&args(&[("a", "{b}"), ("b", "x y")]))).unwrap();
+        assert_eq!(argv, vec!["echo", "{b}:x y", "x y"]);
+    }
+
+    #[test]
+    fn value_opening_an_argument_may_not_be_an_option() {
+        let tool = tool(&["git", "log", "{rev}", "--grep={pattern}"]);
+        assert!(tool.build_argv(Some(&args(&[("rev", "--all"), ("pattern", "x")]))).is_err());
+
+        let argv = tool.build_argv(Some(&args(&[("rev", "main"), ("pattern", "-x")]))).unwrap();
+        assert_eq!(argv, vec!["git", "log", "main", "--grep=-x"]);
+    }
+
+    #[test]
+    fn missing_or_non_string_arguments_are_refused() {
+        let tool = tool(&["cat", "{path}"]);
+        assert!(tool.build_argv(None).is_err());
+        let number = Struct {
+            fields: BTreeMap::from([("path".to_string(), Value::from(1.0))]),
+        };
+        assert!(tool.build_argv(Some(&number)).is_err());
+    }
+
+    #[test]
+    fn tools_taken_before_a_reload_are_kept() {
+        init_config();
+        let file = temp_dir("command-reload").join("tools.json");
+        std::fs::write(
+            &file,
+            r#"[{"name": "greet", "description": "", "command_template": ["echo", "{who}"]}]"#,
+        )
+        .unwrap();
+        load(Some(&file), &[]).unwrap();
+        let taken = CommandTools::current();
+        load(None, &[]).unwrap();
+
+        assert!(!CommandTools::current().exists("greet"));
+        assert!(taken.exists("greet"));
+        let call = FunctionCall {
+            id: String::new(),
+            name: "greet".to_string(),
+            args: Some(args(&[("who", "world")])),
+        };
+        let result = handle_command(&taken, call, &Progress::none()).response.unwrap();
+        assert!(matches!(field(&result, "stdout"), Some(Kind::StringValue(s)) if s == "world\n"));
+    }
+
+    #[test]
+    fn output_and_status_of_a_command() {
+        let result = run(
+            &argv(&["sh", "-c", "echo out; echo err >&2; exit 3"]),
+            Duration::from_secs(5),
+            &Progress::none(),
+        )
+        .unwrap();
+        assert!(matches!(field(&result, "stdout"), Some(Kind::StringValue(s)) if s == "out\n"));
+        assert!(matches!(field(&result, "stderr"), Some(Kind::StringValue(s)) if s == "err\n"));
+        assert!(matches!(field(&result, "status"), Some(Kind::NumberValue(n)) if *n == 3.0));
+        assert!(matches!(field(&result, "timed_out"), Some(Kind::BoolValue(false))));
+    }
+
+    #[test]
+    fn timeout_holds_while_a_grandchild_keeps_the_output_open() {
+        let start = Instant::now();
+        let result = run(
+            &argv(&["sh", "-c", "sleep 30 & echo started"]),
+            Duration::from_millis(300),
+            &Progress::none(),
+        )
+        .unwrap();
+
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(matches!(field(&result, "stdout"), Some(Kind::StringValue(s)) if s == "started\n"));
+        assert!(matches!(field(&result, "timed_out"), Some(Kind::BoolValue(true))));
+    }
+}
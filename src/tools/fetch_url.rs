@@ -0,0 +1,402 @@
+use crate::tools::args::{get_opt_u64, get_string};
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use crate::CONFIG;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use bytes::Bytes;
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use http::{header, Request, Response, Uri};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Incoming;
+use hyper_util::rt::TokioIo;
+use prost_types::{Struct, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{lookup_host, TcpStream};
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::rustls::{crypto, ClientConfig, RootCertStore};
+use tokio_rustls::TlsConnector;
+
+/// Bytes of body returned when `max_bytes` isn't given, and the most it may ask for
+const DEFAULT_MAX_BYTES: u64 = 1024 * 1024;
+const MAX_MAX_BYTES: u64 = 8 * 1024 * 1024;
+
+const MAX_REDIRECTS: usize = 5;
+
+struct Fetched {
+    url: String,
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+    truncated: bool,
+}
+
+fn is_text(content_type: &str) -> bool {
+    content_type.starts_with("text/")
+        || ["json", "xml", "javascript"]
+            .iter()
+            .any(|t| content_type.contains(t))
+}
+
+fn respond_result(fetched: Fetched) -> Struct {
+    let mut fields = BTreeMap::from([
+        ("url".to_string(), Value::from(fetched.url)),
+        ("status".to_string(), Value::from(fetched.status as f64)),
+        ("bytes".to_string(), Value::from(fetched.body.len() as f64)),
+        ("body_truncated".to_string(), Value::from(fetched.truncated)),
+    ]);
+
+    let text = fetched.content_type.as_deref().is_some_and(is_text);
+    match String::from_utf8(fetched.body) {
+        Ok(body) if text => {
+            fields.insert("body".to_string(), Value::from(body));
+        }
+        Ok(body) => {
+            fields.insert("body_base64".to_string(), Value::from(BASE64.encode(body)));
+        }
+        Err(e) => {
+            fields.insert("body_base64".to_string(), Value::from(BASE64.encode(e.as_bytes())));
+        }
+    }
+
+    if let Some(content_type) = fetched.content_type {
+        fields.insert("content_type".to_string(), Value::from(content_type));
+    }
+
+    Struct { fields }
+}
+
+/// Whether `ip` is reachable from anywhere, i.e. not loopback, private, link-local or the like
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_unspecified()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (b & 0xc0) == 64) /* shared address space */)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+fn tls_config() -> Arc<ClientConfig> {
+    static TLS_CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+
+    TLS_CONFIG
+        .get_or_init(|| {
+            let mut roots = RootCertStore::empty();
+            roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+
+            let config = ClientConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_root_certificates(roots)
+                .with_no_client_auth();
+            Arc::new(config)
+        })
+        .clone()
+}
+
+async fn send<T>(io: T, req: Request<Empty<Bytes>>) -> Result<Response<Incoming>, String>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(io))
+        .await
+        .map_err(|e| e.to_string())?;
+    tokio::spawn(conn);
+
+    sender.send_request(req).await.map_err(|e| e.to_string())
+}
+
+async fn get(uri: &Uri, allow_private: bool) -> Result<Response<Incoming>, String> {
+    let https = match uri.scheme_str() {
+        Some("https") => true,
+        Some("http") => false,
+        _ => return Err(format!("'{}' is not an http or https URL", uri)),
+    };
+    let (Some(host), Some(authority)) = (uri.host(), uri.authority()) else {
+        return Err(format!("'{}' has no host", uri));
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = uri.port_u16().unwrap_or(if https { 443 } else { 80 });
+
+    let addrs: Vec<SocketAddr> = lookup_host((host, port))
+        .await
+        .map_err(|e| format!("Cannot resolve '{}': {}", host, e))?
+        .collect();
+
+    // Connecting to the very addresses checked leaves no room for DNS rebinding
+    if !allow_private && let Some(addr) = addrs.iter().find(|addr| !is_public(addr.ip())) {
+        return Err(format!(
+            "'{}' resolves to non-public address {}; fetching it is not allowed",
+            host,
+            addr.ip()
+        ));
+    }
+
+    let tcp = TcpStream::connect(&addrs[..])
+        .await
+        .map_err(|e| format!("Cannot connect to '{}': {}", authority, e))?;
+
+    let req = Request::get(uri.path_and_query().map_or("/", |p| p.as_str()))
+        .header(header::HOST, authority.as_str())
+        .header(header::USER_AGENT, concat!("yas/", env!("CARGO_PKG_VERSION")))
+        .body(Empty::<Bytes>::new())
+        .map_err(|e| e.to_string())?;
+
+    if !https {
+        return send(tcp, req).await;
+    }
+
+    let name = ServerName::try_from(host.to_string()).map_err(|e| e.to_string())?;
+    let tls = TlsConnector::from(tls_config())
+        .connect(name, tcp)
+        .await
+        .map_err(|e| format!("TLS handshake with '{}' failed: {}", authority, e))?;
+    send(tls, req).await
+}
+
+/// Target of a redirect from `uri`, which may be given relative to it
+fn redirect_target(uri: &Uri, location: &str) -> Result<Uri, String> {
+    let target: Uri = location
+        .parse()
+        .map_err(|e| format!("Invalid redirect to '{}': {}", location, e))?;
+    if target.scheme().is_some() {
+        return Ok(target);
+    }
+
+    let path = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        let base = uri.path().rsplit_once('/').map_or("", |(dir, _)| dir);
+        format!("{}/{}", base, location)
+    };
+
+    Uri::builder()
+        .scheme(uri.scheme_str().unwrap_or("http"))
+        .authority(uri.authority().map_or("", |a| a.as_str()))
+        .path_and_query(path)
+        .build()
+        .map_err(|e| format!("Invalid redirect to '{}': {}", location, e))
+}
+
+async fn fetch(url: &str, max_bytes: u64, allow_private: bool) -> Result<Fetched, String> {
+    let mut uri: Uri = url
+        .parse()
+        .map_err(|e| format!("Invalid URL '{}': {}", url, e))?;
+
+    for _ in 0..=MAX_REDIRECTS {
+        let resp = get(&uri, allow_private).await?;
+
+        if resp.status().is_redirection()
+            && let Some(location) = resp.headers().get(header::LOCATION)
+        {
+            let location = location.to_str().map_err(|e| e.to_string())?;
+            uri = redirect_target(&uri, location)?;
+            continue;
+        }
+
+        let status = resp.status().as_u16();
+        let content_type = resp
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string);
+
+        let mut body = resp.into_body();
+        let mut bytes = vec![];
+        let mut truncated = false;
+        while let Some(frame) = body.frame().await {
+            let frame = frame.map_err(|e| e.to_string())?;
+            let Some(data) = frame.data_ref() else {
+                continue;
+            };
+
+            let room = max_bytes as usize - bytes.len();
+            if data.len() > room {
+                bytes.extend_from_slice(&data[..room]);
+                truncated = true;
+                break;
+            }
+            bytes.extend_from_slice(data);
+        }
+
+        return Ok(Fetched {
+            url: uri.to_string(),
+            status,
+            content_type,
+            body: bytes,
+            truncated,
+        });
+    }
+
+    Err(format!("Gave up after {} redirects", MAX_REDIRECTS))
+}
+
+/// Runs `fetch` to completion on a runtime of its own, as tools are called from both
+/// blocking threads and async tasks
+fn fetch_blocking(url: String, max_bytes: u64, timeout: Duration) -> Result<Fetched, String> {
+    let allow_private = CONFIG.get().unwrap().allow_private_fetch;
+
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        runtime.block_on(async {
+            tokio::time::timeout(timeout, fetch(&url, max_bytes, allow_private))
+                .await
+                .unwrap_or_else(|_| Err(format!("Fetching '{}' timed out after {:?}", url, timeout)))
+        })
+    })
+    .join()
+    .unwrap_or_else(|_| Err("Fetch failed unexpectedly".to_string()))
+}
+
+pub fn handle_fetch_url(call: FunctionCall, _: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "fetch_url");
+
+    let Some(args) = call.args.as_ref() else {
+        return FunctionResponse{
+            id: call.id,
+            name: call.name,
+            response: Some(ToolError::InvalidArgs("Argument is none".to_string()).into()),
+        };
+    };
+
+    let (url, max_bytes) = match (get_string(args, "url"), get_opt_u64(args, "max_bytes")) {
+        (Ok(url), Ok(max_bytes)) => (url, max_bytes),
+        (Err(e), _) | (_, Err(e)) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+    let max_bytes = max_bytes.unwrap_or(DEFAULT_MAX_BYTES).min(MAX_MAX_BYTES);
+
+    let resp = match fetch_blocking(url.to_string(), max_bytes, CONFIG.get().unwrap().tool_timeout) {
+        Ok(fetched) => respond_result(fetched),
+        Err(e) => ToolError::ExecutionFailed(e).into(),
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+pub fn fetch_url_decl() -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: "fetch_url".to_string(),
+        description: r#"
+        Fetch a web resource with HTTP GET, e.g. to read documentation.
+        Only http and https URLs are allowed; redirects are followed.
+        Addresses on the user's own machine or network are refused unless the user allowed them.
+        Text bodies (text/*, JSON, XML, JavaScript) are returned as `body`, others base64-encoded as `body_base64`.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("url".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "URL to fetch".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("max_bytes".to_string(), Schema {
+                    r#type: 3, /* INTEGER */
+                    description: format!(
+                        "(Optional) Most bytes of body to return; {} by default, at most {}",
+                        DEFAULT_MAX_BYTES, MAX_MAX_BYTES
+                    ),
+                    nullable: true,
+                    ..Schema::default()
+                }),
+            ]),
+            required: vec!["url".to_string()],
+            ..Schema::default()
+        }),
+        response: Some(Schema{
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("error".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Error during fetch".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+                ("url".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) URL fetched in the end, after redirects".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("status".to_string(), Schema{
+                    r#type: 3, /* INTEGER */
+                    description: "(Optional) HTTP status code".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("content_type".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Content-Type of the response, if given".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("body".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Body, if it is text".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("body_base64".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Body encoded in base64, if it isn't text".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("bytes".to_string(), Schema{
+                    r#type: 3, /* INTEGER */
+                    description: "(Optional) Bytes of body returned".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("body_truncated".to_string(), Schema{
+                    r#type: 4, /* BOOLEAN */
+                    description: "(Optional) Whether the body was cut at `max_bytes`".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
@@ -0,0 +1,188 @@
+use crate::mime;
+use crate::tools::args::get_string;
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::{Struct, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+
+/// Bytes read from the start of a file to tell its type, as `read_fs` reads to spot binaries
+const HEAD_LENGTH: u64 = 8 * 1024;
+
+/// Values of `detected_by`
+const DETECTED_BY: [&str; 2] = ["content", "extension"];
+
+struct FileType {
+    mime_type: &'static str,
+    description: String,
+    is_text: bool,
+    /// Index into `DETECTED_BY`
+    detected_by: usize,
+}
+
+/// Whether `head` reads as UTF-8 text, allowing a character cut off at its end
+fn is_text(head: &[u8]) -> bool {
+    if head.contains(&0) {
+        return false;
+    }
+
+    match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    }
+}
+
+/// Type of the file at `path`: by signature first, then by extension, then by whether its
+/// start reads as text
+fn filetype_fs(path: &str) -> Result<FileType, String> {
+    let mut head = vec![];
+    File::open(path)
+        .and_then(|file| file.take(HEAD_LENGTH).read_to_end(&mut head))
+        .map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+
+    if let Some(sniffed) = mime::sniff(&head) {
+        return Ok(FileType {
+            mime_type: sniffed.name,
+            description: sniffed.description.to_string(),
+            is_text: false,
+            detected_by: 0,
+        });
+    }
+
+    let text = is_text(&head);
+    if let Some(mime) = mime::from_path(path)
+        && mime.is_text == text
+    {
+        return Ok(FileType {
+            mime_type: mime.name,
+            description: match mime.is_text {
+                true => "Text, judged by its extension".to_string(),
+                false => "Binary data, judged by its extension".to_string(),
+            },
+            is_text: mime.is_text,
+            detected_by: 1,
+        });
+    }
+
+    let (mime_type, description) = match (head.is_empty(), text) {
+        (true, _) => ("application/x-empty", "Empty file"),
+        (false, true) => ("text/plain", "UTF-8 text"),
+        (false, false) => ("application/octet-stream", "Binary data of unknown format"),
+    };
+
+    Ok(FileType {
+        mime_type,
+        description: description.to_string(),
+        is_text: text,
+        detected_by: 0,
+    })
+}
+
+pub fn handle_filetype_fs(call: FunctionCall, _: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "filetype_fs");
+
+    let Some(args) = call.args.as_ref() else {
+        return FunctionResponse{
+            id: call.id,
+            name: call.name,
+            response: Some(ToolError::InvalidArgs("Argument is none".to_string()).into()),
+        };
+    };
+
+    let path = match get_string(args, "path") {
+        Ok(path) => path,
+        Err(e) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+
+    let resp = match filetype_fs(path) {
+        Ok(file_type) => Struct {
+            fields: BTreeMap::from([
+                ("mime_type".to_string(), Value::from(file_type.mime_type)),
+                ("description".to_string(), Value::from(file_type.description)),
+                ("is_text".to_string(), Value::from(file_type.is_text)),
+                ("detected_by".to_string(), Value::from(DETECTED_BY[file_type.detected_by])),
+            ]),
+        },
+        Err(e) => ToolError::ExecutionFailed(e).into(),
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+pub fn filetype_fs_decl() -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: "filetype_fs".to_string(),
+        description: r#"
+        Tell the type of a file on user's filesystem from its first bytes, like `file`.
+        Useful to decide how to handle a file (text, image, archive, ...) before reading it.
+        Known signatures come first, then the extension, then whether the file starts as UTF-8 text.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("path".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "Path of file to inspect".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            required: vec!["path".to_string()],
+            ..Schema::default()
+        }),
+        response: Some(Schema{
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("mime_type".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Detected MIME type (e.g. `image/png`)".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("description".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) What the file is, for people (e.g. `PNG image`)".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("is_text".to_string(), Schema{
+                    r#type: 4, /* BOOLEAN */
+                    description: "(Optional) Whether `read_fs` can read the file as text".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("detected_by".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Whether the type came from the file's content or only its extension".to_string(),
+                    nullable: false,
+                    format: "enum".to_string(),
+                    r#enum: DETECTED_BY.iter().map(|v| v.to_string()).collect(),
+                    ..Schema::default()
+                }),
+                ("error".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Error while reading the file".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
@@ -0,0 +1,125 @@
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use crate::CONFIG;
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use prost_types::{Struct, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::env::{var, vars};
+
+fn respond_variables(variables: BTreeMap<String, String>) -> Struct {
+    let variables = Struct {
+        fields: variables
+            .into_iter()
+            .map(|(k, v)| (k, Value::from(v)))
+            .collect(),
+    };
+
+    Struct {
+        fields: BTreeMap::from([
+            ("variables".to_string(), Value::from(Kind::StructValue(variables)))
+        ]),
+    }
+}
+
+/// Secrets of the server itself, never readable whatever the allowlist says
+const DENIED: [&str; 2] = ["GEMINI_API_KEY", "YAS_ADMIN_TOKEN"];
+
+fn is_allowed(name: &str) -> bool {
+    !DENIED.contains(&name)
+        && CONFIG
+            .get()
+            .unwrap()
+            .env_allow
+            .iter()
+            .any(|prefix| name.starts_with(prefix.as_str()))
+}
+
+fn get_env(name: Option<&str>) -> Result<BTreeMap<String, String>, ToolError> {
+    let Some(name) = name else {
+        return Ok(vars().filter(|(k, _)| is_allowed(k)).collect());
+    };
+
+    if !is_allowed(name) {
+        return Err(ToolError::Denied(format!("Variable '{}' is not allowed to be read", name)));
+    }
+
+    match var(name) {
+        Ok(value) => Ok(BTreeMap::from([(name.to_string(), value)])),
+        Err(_) => Err(ToolError::ExecutionFailed(format!("Variable '{}' is not set", name))),
+    }
+}
+
+pub fn handle_get_env(call: FunctionCall, _: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "get_env");
+
+    let name = match call.args.as_ref().and_then(|args| args.fields.get("name")) {
+        None | Some(Value { kind: None }) | Some(Value { kind: Some(Kind::NullValue(_)) }) => None,
+        Some(Value { kind: Some(Kind::StringValue(s)) }) => Some(s.as_str()),
+        Some(_) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs("String argument 'name' is not a string".to_string()).into()),
+            };
+        }
+    };
+
+    let resp = match get_env(name) {
+        Ok(variables) => respond_variables(variables),
+        Err(e) => e.into(),
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+pub fn get_env_decl() -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: "get_env".to_string(),
+        description: r#"
+        Read environment variables of the server process.
+        Only variables allowed by the server's configuration can be read.
+        Without `name`, every allowed variable is returned.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([(
+                "name".to_string(),
+                Schema {
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Name of variable to read".to_string(),
+                    nullable: true,
+                    ..Schema::default()
+                },
+            )]),
+            required: vec![],
+            ..Schema::default()
+        }),
+        response: Some(Schema{
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("error".to_string(), Schema{
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Why the variable couldn't be read".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+                ("variables".to_string(), Schema{
+                    r#type: 6, /* OBJECT */
+                    description: "(Optional) Map of variable name to value".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
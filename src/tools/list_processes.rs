@@ -0,0 +1,219 @@
+use crate::tools::args::get_opt_u64;
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use prost_types::{Struct, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// Processes returned when the call doesn't give `limit`
+const DEFAULT_LIMIT: u64 = 100;
+
+/// Most processes returned whatever `limit` says
+const MAX_LIMIT: u64 = 1000;
+
+/// Longest command line returned; longer ones are cut
+const MAX_COMMAND_CHARS: usize = 256;
+
+struct Process {
+    pid: u32,
+    ppid: u32,
+    name: String,
+    command: String,
+    /// Resident memory; absent for kernel threads
+    rss_kib: Option<u64>,
+}
+
+/// `name` and `ppid` from `/proc/<pid>/stat`, whose name is in parentheses and may hold any
+/// character, spaces and `)` included
+fn parse_stat(stat: &str) -> Option<(String, u32)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+
+    // After the name come the state, then the parent's pid
+    let ppid = stat[close + 1..].split_whitespace().nth(1)?.parse().ok()?;
+    Some((name, ppid))
+}
+
+/// `VmRSS` of `/proc/<pid>/status`, in KiB
+fn parse_rss(status: &str) -> Option<u64> {
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))
+        .and_then(|rest| rest.split_whitespace().next())
+        .and_then(|kib| kib.parse().ok())
+}
+
+/// Process `pid`, or `None` if it exited while being read
+fn read_process(pid: u32) -> Option<Process> {
+    let dir = format!("/proc/{}", pid);
+    let (name, ppid) = parse_stat(&fs::read_to_string(format!("{}/stat", dir)).ok()?)?;
+    let rss_kib = fs::read_to_string(format!("{}/status", dir)).ok().as_deref().and_then(parse_rss);
+
+    let cmdline = fs::read(format!("{}/cmdline", dir)).unwrap_or_default();
+    let mut command = String::from_utf8_lossy(&cmdline)
+        .trim_end_matches('\0')
+        .replace('\0', " ");
+    if command.is_empty() {
+        command = format!("[{}]", name);
+    }
+    if let Some((cut, _)) = command.char_indices().nth(MAX_COMMAND_CHARS) {
+        command.truncate(cut);
+        command.push('…');
+    }
+
+    Some(Process { pid, ppid, name, command, rss_kib })
+}
+
+/// Running processes, largest resident memory first, at most `limit` of them
+fn list_processes(limit: u64) -> Result<Struct, ToolError> {
+    let entries = fs::read_dir("/proc")
+        .map_err(|e| ToolError::ExecutionFailed(format!("Cannot read '/proc': {}", e)))?;
+
+    let mut processes: Vec<Process> = entries
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .filter_map(read_process)
+        .collect();
+    let total = processes.len();
+
+    processes.sort_by_key(|process| (Reverse(process.rss_kib), process.pid));
+    let results: Vec<Value> = processes
+        .into_iter()
+        .take(limit.min(MAX_LIMIT) as usize)
+        .map(|process| {
+            let mut fields = BTreeMap::from([
+                ("pid".to_string(), Value::from(process.pid)),
+                ("ppid".to_string(), Value::from(process.ppid)),
+                ("name".to_string(), Value::from(process.name)),
+                ("command".to_string(), Value::from(process.command)),
+            ]);
+            if let Some(rss_kib) = process.rss_kib {
+                fields.insert("rss_kib".to_string(), Value::from(rss_kib as f64));
+            }
+            Value::from(Kind::StructValue(Struct { fields }))
+        })
+        .collect();
+
+    Ok(Struct {
+        fields: BTreeMap::from([
+            ("processes".to_string(), Value::from(results)),
+            ("total".to_string(), Value::from(total as f64)),
+        ]),
+    })
+}
+
+pub fn handle_list_processes(call: FunctionCall, _: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "list_processes");
+
+    let limit = match call.args.as_ref().map(|args| get_opt_u64(args, "limit")) {
+        None => None,
+        Some(Ok(limit)) => limit,
+        Some(Err(e)) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+
+    let resp = match list_processes(limit.unwrap_or(DEFAULT_LIMIT)) {
+        Ok(resp) => resp,
+        Err(e) => e.into(),
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+pub fn list_processes_decl() -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: "list_processes".to_string(),
+        description: r#"
+        List processes running on user's machine, like `ps`, largest resident memory first.
+        Useful to diagnose what is using memory or whether a program is running.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: true,
+            properties: HashMap::from([
+                ("limit".to_string(), Schema {
+                    r#type: 3, /* INTEGER */
+                    description: format!("Most processes to return; {} by default, at most {}", DEFAULT_LIMIT, MAX_LIMIT),
+                    nullable: true,
+                    ..Schema::default()
+                }),
+            ]),
+            ..Schema::default()
+        }),
+        response: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("processes".to_string(), Schema {
+                    r#type: 5, /* ARRAY */
+                    description: "(Optional) Running processes, largest resident memory first".to_string(),
+                    nullable: false,
+                    items: Some(Box::new(Schema {
+                        r#type: 6, /* OBJECT */
+                        nullable: false,
+                        properties: HashMap::from([
+                            ("pid".to_string(), Schema {
+                                r#type: 3, /* INTEGER */
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                            ("ppid".to_string(), Schema {
+                                r#type: 3, /* INTEGER */
+                                description: "Pid of the parent process".to_string(),
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                            ("name".to_string(), Schema {
+                                r#type: 1, /* STRING */
+                                description: "Executable name, at most 15 characters".to_string(),
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                            ("command".to_string(), Schema {
+                                r#type: 1, /* STRING */
+                                description: format!("Command line, cut at {} characters; the name in brackets for kernel threads", MAX_COMMAND_CHARS),
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                            ("rss_kib".to_string(), Schema {
+                                r#type: 3, /* INTEGER */
+                                description: "(Optional) Resident memory in KiB; absent for kernel threads".to_string(),
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                        ]),
+                        ..Schema::default()
+                    })),
+                    ..Schema::default()
+                }),
+                ("total".to_string(), Schema {
+                    r#type: 3, /* INTEGER */
+                    description: "(Optional) Number of processes running, including those past `limit`".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Why processes couldn't be listed".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
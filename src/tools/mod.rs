@@ -1,8 +1,354 @@
+mod append_fs;
+mod args;
+mod breakdown_fs;
+mod command;
+mod fetch_url;
+mod filetype_fs;
+mod find_and_read;
+mod get_env;
+mod list_processes;
 mod read_fs;
+mod recent_fs;
 mod search_fs;
+mod symbol_fs;
+mod wc_fs;
 
-pub use search_fs::handle_search_fs;
-pub use search_fs::search_fs_decl;
+use crate::config::Config;
+pub use command::CommandTools;
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::{Struct, Value};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
-pub use read_fs::handle_read_fs;
-pub use read_fs::read_fs_decl;
+/// What a running tool tells the user before it returns
+pub enum Update {
+    /// How far it got, superseded by the next status
+    Status(Struct),
+    /// Part of the results, in order; the final response still holds all of them
+    Output(Struct),
+}
+
+/// Sink for intermediate results of a running tool, shown to the user but not the model
+pub struct Progress(Option<Box<dyn Fn(Update) + Send>>);
+
+impl Progress {
+    pub fn new(report: impl Fn(Update) + Send + 'static) -> Self {
+        Self(Some(Box::new(report)))
+    }
+
+    /// Progress that goes nowhere
+    pub fn none() -> Self {
+        Self(None)
+    }
+
+    pub fn report(&self, progress: Struct) {
+        if let Some(report) = &self.0 {
+            report(Update::Status(progress));
+        }
+    }
+
+    /// Sends results found so far, for a tool to make long work visible as it goes
+    pub fn output(&self, output: Struct) {
+        if let Some(report) = &self.0 {
+            report(Update::Output(output));
+        }
+    }
+}
+
+pub type ToolHandler = fn(FunctionCall, &Progress) -> FunctionResponse;
+
+/// What handles calls to one tool
+pub enum Handler {
+    BuiltIn(ToolHandler),
+    /// A command tool, looked up in these when called
+    Command(CommandTools),
+}
+
+impl Handler {
+    pub fn handle(&self, call: FunctionCall, progress: &Progress) -> FunctionResponse {
+        match self {
+            Handler::BuiltIn(handle) => handle(call, progress),
+            Handler::Command(tools) => command::handle_command(tools, call, progress),
+        }
+    }
+}
+
+/// Field of a tool result meant for the user alone, such as highlighted HTML: it's streamed
+/// to the client but kept out of history, so the model never sees it
+pub const DISPLAY_FIELD: &str = "display";
+
+/// Why a tool call has no result, sent to the model as `{"error": message, "error_kind": kind}`
+/// whichever tool it was; those reading files through `read_fs` add a finer `kind`
+pub enum ToolError {
+    /// No enabled tool has the called name
+    UnknownTool(String),
+    /// Arguments are missing or of the wrong type
+    InvalidArgs(String),
+    /// The tool ran but couldn't do what was asked
+    ExecutionFailed(String),
+    /// The tool didn't finish in time
+    Timeout(String),
+    /// The call wasn't allowed to run, or to do what it asked
+    Denied(String),
+}
+
+/// Values of `error_kind`
+const ERROR_KINDS: [&str; 5] = ["unknown_tool", "invalid_args", "execution_failed", "timeout", "denied"];
+
+impl ToolError {
+    fn kind(&self) -> &'static str {
+        match self {
+            ToolError::UnknownTool(_) => ERROR_KINDS[0],
+            ToolError::InvalidArgs(_) => ERROR_KINDS[1],
+            ToolError::ExecutionFailed(_) => ERROR_KINDS[2],
+            ToolError::Timeout(_) => ERROR_KINDS[3],
+            ToolError::Denied(_) => ERROR_KINDS[4],
+        }
+    }
+}
+
+impl From<ToolError> for Struct {
+    fn from(error: ToolError) -> Self {
+        let kind = error.kind();
+        let message = match error {
+            ToolError::UnknownTool(message)
+            | ToolError::InvalidArgs(message)
+            | ToolError::ExecutionFailed(message)
+            | ToolError::Timeout(message)
+            | ToolError::Denied(message) => message,
+        };
+
+        Struct {
+            fields: BTreeMap::from([
+                ("error".to_string(), Value::from(message)),
+                ("error_kind".to_string(), Value::from(kind)),
+            ]),
+        }
+    }
+}
+
+/// Schema of `error_kind` in tool responses
+pub(super) fn error_kind_schema() -> Schema {
+    Schema {
+        r#type: 1, /* STRING */
+        description: "(Optional) Class of error: bad arguments can be fixed and retried, a denial can't".to_string(),
+        nullable: false,
+        format: "enum".to_string(),
+        r#enum: ERROR_KINDS.iter().map(|v| v.to_string()).collect(),
+        ..Schema::default()
+    }
+}
+
+struct ToolDef {
+    name: &'static str,
+    decl: fn() -> FunctionDeclaration,
+    handle: ToolHandler,
+    /// Whether the configuration allows this tool
+    allowed: fn(&Config) -> bool,
+    /// Whether the tool has no side effects, so calls to it may run concurrently
+    read_only: bool,
+}
+
+impl ToolDef {
+    fn enabled(&self, config: &Config) -> bool {
+        !config.no_tools && (self.allowed)(config)
+    }
+}
+
+fn always(_: &Config) -> bool {
+    true
+}
+
+/// Tools modifying the filesystem are gated by `Config::allow_destructive`
+fn destructive(config: &Config) -> bool {
+    config.allow_destructive
+}
+
+const TOOLS: [ToolDef; 12] = [
+    ToolDef {
+        name: "search_fs",
+        decl: search_fs::search_fs_decl,
+        handle: search_fs::handle_search_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "read_fs",
+        decl: read_fs::read_fs_decl,
+        handle: read_fs::handle_read_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "find_and_read",
+        decl: find_and_read::find_and_read_decl,
+        handle: find_and_read::handle_find_and_read,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "wc_fs",
+        decl: wc_fs::wc_fs_decl,
+        handle: wc_fs::handle_wc_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "recent_fs",
+        decl: recent_fs::recent_fs_decl,
+        handle: recent_fs::handle_recent_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "breakdown_fs",
+        decl: breakdown_fs::breakdown_fs_decl,
+        handle: breakdown_fs::handle_breakdown_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "filetype_fs",
+        decl: filetype_fs::filetype_fs_decl,
+        handle: filetype_fs::handle_filetype_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "symbol_fs",
+        decl: symbol_fs::symbol_fs_decl,
+        handle: symbol_fs::handle_symbol_fs,
+        allowed: always,
+        read_only: true,
+    },
+    ToolDef {
+        name: "append_fs",
+        decl: append_fs::append_fs_decl,
+        handle: append_fs::handle_append_fs,
+        allowed: destructive,
+        read_only: false,
+    },
+    ToolDef {
+        name: "get_env",
+        decl: get_env::get_env_decl,
+        handle: get_env::handle_get_env,
+        allowed: |config| !config.env_allow.is_empty(),
+        read_only: true,
+    },
+    ToolDef {
+        name: "fetch_url",
+        decl: fetch_url::fetch_url_decl,
+        handle: fetch_url::handle_fetch_url,
+        allowed: |config| config.enable_fetch_url,
+        read_only: true,
+    },
+    ToolDef {
+        name: "list_processes",
+        decl: list_processes::list_processes_decl,
+        handle: list_processes::handle_list_processes,
+        allowed: |config| config.enable_proc_tools,
+        read_only: true,
+    },
+];
+
+/// Canonical form of `path` for a tool to write, if it's within `Config::write_root`.
+///
+/// The file itself needn't exist, but its directory must. Symlinks are resolved first,
+/// so neither `..` nor a link can lead out of the root.
+pub(super) fn writable_path(config: &Config, path: &str) -> Result<PathBuf, String> {
+    let Some(root) = &config.write_root else {
+        return Ok(PathBuf::from(path));
+    };
+
+    let path = Path::new(path);
+    let resolved = match path.canonicalize() {
+        Ok(resolved) => resolved,
+        // A dangling link would be followed to wherever it points once the file is created
+        Err(_) if path.is_symlink() => {
+            return Err(format!("'{}' is a link to a missing file", path.display()));
+        }
+        Err(_) => {
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                return Err(format!("'{}' is not a file path", path.display()));
+            };
+            let parent = if parent.as_os_str().is_empty() { Path::new(".") } else { parent };
+            parent
+                .canonicalize()
+                .map_err(|e| format!("Cannot resolve '{}': {}", parent.display(), e))?
+                .join(name)
+        }
+    };
+
+    if !resolved.starts_with(root) {
+        return Err(format!(
+            "'{}' is outside the write root '{}'",
+            path.display(),
+            root.display()
+        ));
+    }
+
+    Ok(resolved)
+}
+
+/// Registers the command tools of `Config::tools_file`, replacing any registered before
+pub fn load_command_tools(config: &Config) -> Result<(), String> {
+    let reserved: Vec<&str> = TOOLS.iter().map(|tool| tool.name).collect();
+    command::load(config.tools_file.as_deref(), &reserved)
+}
+
+/// Fields naming the call a response answers, set in every response given to the model
+/// so results of different calls can't be told apart only by their position
+pub const TOOL_FIELD: &str = "tool";
+pub const CALL_ID_FIELD: &str = "call_id";
+
+fn with_call_fields(mut decl: FunctionDeclaration) -> FunctionDeclaration {
+    if let Some(response) = &mut decl.response {
+        response.properties.insert(TOOL_FIELD.to_string(), Schema {
+            r#type: 1, /* STRING */
+            description: "Name of the tool that produced this response".to_string(),
+            nullable: false,
+            ..Schema::default()
+        });
+        response.properties.insert(CALL_ID_FIELD.to_string(), Schema {
+            r#type: 1, /* STRING */
+            description: "Id of the function call this response answers".to_string(),
+            nullable: false,
+            ..Schema::default()
+        });
+    }
+    decl
+}
+
+/// Declarations of every tool enabled under `config`
+pub fn declarations(config: &Config) -> Vec<FunctionDeclaration> {
+    if config.no_tools {
+        return vec![];
+    }
+
+    TOOLS
+        .iter()
+        .filter(|tool| tool.enabled(config))
+        .map(|tool| (tool.decl)())
+        .chain(CommandTools::current().declarations())
+        .map(with_call_fields)
+        .collect()
+}
+
+/// Handler of tool named `name`, if it's enabled under `config` or one of `commands`
+pub fn handler(config: &Config, commands: &CommandTools, name: &str) -> Option<Handler> {
+    if config.no_tools {
+        return None;
+    }
+
+    TOOLS
+        .iter()
+        .find(|tool| tool.name == name && tool.enabled(config))
+        .map(|tool| Handler::BuiltIn(tool.handle))
+        .or_else(|| commands.exists(name).then(|| Handler::Command(commands.clone())))
+}
+
+/// Whether calls to tool `name` can run alongside others; command tools may do anything
+pub fn is_read_only(name: &str) -> bool {
+    TOOLS.iter().any(|tool| tool.name == name && tool.read_only)
+}
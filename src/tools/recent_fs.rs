@@ -0,0 +1,173 @@
+use crate::tools::args::{get_opt_u64, get_string};
+use crate::tools::search_fs::{search_fs, SearchOptions};
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use prost_types::{Struct, Value};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Files returned when the call doesn't give `limit`
+const DEFAULT_LIMIT: u64 = 20;
+
+/// Files matching `pattern`, most recently accessed first, at most `limit` of them
+fn recent_fs(pattern: &str, limit: u64, progress: &Progress) -> Struct {
+    let (entries, errors) = search_fs(pattern, &SearchOptions::default(), progress);
+    let mut files: Vec<_> = entries.into_iter().filter(|entry| entry.is_file()).collect();
+    let total = files.len();
+
+    files.sort_by_key(|entry| Reverse(entry.atime));
+    let results: Vec<Value> = files
+        .into_iter()
+        .take(limit as usize)
+        .map(|entry| {
+            Value::from(Kind::StructValue(Struct {
+                fields: BTreeMap::from([
+                    ("path".to_string(), Value::from(entry.path)),
+                    ("atime".to_string(), Value::from(entry.atime as f64)),
+                ]),
+            }))
+        })
+        .collect();
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let errors: Vec<Value> = errors.into_iter().map(Value::from).collect();
+
+    Struct {
+        fields: BTreeMap::from([
+            ("results".to_string(), Value::from(results)),
+            ("total".to_string(), Value::from(total as f64)),
+            ("now".to_string(), Value::from(now as f64)),
+            ("errors".to_string(), Value::from(errors)),
+        ]),
+    }
+}
+
+pub fn handle_recent_fs(call: FunctionCall, progress: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "recent_fs");
+
+    let Some(args) = call.args.as_ref() else {
+        return FunctionResponse{
+            id: call.id,
+            name: call.name,
+            response: Some(ToolError::InvalidArgs("Argument is none".to_string()).into()),
+        };
+    };
+
+    let arguments = get_string(args, "pattern").and_then(|pattern| {
+        let limit = get_opt_u64(args, "limit")?;
+        Ok((pattern, limit.unwrap_or(DEFAULT_LIMIT)))
+    });
+    let (pattern, limit) = match arguments {
+        Ok(arguments) => arguments,
+        Err(e) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(recent_fs(pattern, limit, progress)),
+    }
+}
+
+pub fn recent_fs_decl() -> FunctionDeclaration {
+    FunctionDeclaration {
+        name: "recent_fs".to_string(),
+        description: r#"
+        List files on user's filesystem matching a glob expression, most recently accessed first.
+        Useful to answer what was recently looked at or worked on.
+        Access times are as the filesystem records them: most are mounted with `relatime`,
+        which updates them at most once a day, and `noatime` mounts don't update them at all.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("pattern".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "Glob expression of files to consider (e.g. `/repos/**/*.rs`)".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("limit".to_string(), Schema {
+                    r#type: 3, /* INTEGER */
+                    description: format!("Most files to return; {} by default", DEFAULT_LIMIT),
+                    nullable: true,
+                    ..Schema::default()
+                }),
+            ]),
+            required: vec!["pattern".to_string()],
+            ..Schema::default()
+        }),
+        response: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("results".to_string(), Schema {
+                    r#type: 5, /* ARRAY */
+                    description: "Matching files, most recently accessed first".to_string(),
+                    nullable: false,
+                    items: Some(Box::new(Schema {
+                        r#type: 6, /* OBJECT */
+                        nullable: false,
+                        properties: HashMap::from([
+                            ("path".to_string(), Schema {
+                                r#type: 1, /* STRING */
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                            ("atime".to_string(), Schema {
+                                r#type: 3, /* INTEGER */
+                                description: "Last access, in seconds since the Unix epoch".to_string(),
+                                nullable: false,
+                                ..Schema::default()
+                            }),
+                        ]),
+                        ..Schema::default()
+                    })),
+                    ..Schema::default()
+                }),
+                ("total".to_string(), Schema {
+                    r#type: 3, /* INTEGER */
+                    description: "Number of matching files, including those past `limit`".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("now".to_string(), Schema {
+                    r#type: 3, /* INTEGER */
+                    description: "Current time, in seconds since the Unix epoch, to compare `atime` with".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("errors".to_string(), Schema {
+                    r#type: 5, /* ARRAY */
+                    description: "Exceptions occurred during the search".to_string(),
+                    nullable: false,
+                    items: Some(Box::new(Schema {
+                        r#type: 1, /* STRING */
+                        nullable: false,
+                        ..Schema::default()
+                    })),
+                    ..Schema::default()
+                }),
+                ("error".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "(Optional) Why the call couldn't run".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+                ("error_kind".to_string(), error_kind_schema()),
+            ]),
+            ..Schema::default()
+        }),
+    }
+}
@@ -1,15 +1,28 @@
-use glob::glob;
+use crate::tools::args::get_bool;
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use crate::CONFIG;
+use glob::{glob_with, MatchOptions, Pattern};
 use google_ai_rs::proto::{FunctionDeclaration, FunctionResponse};
 use google_ai_rs::{FunctionCall, Schema};
 use libc::{S_IFBLK, S_IFCHR, S_IFDIR, S_IFIFO, S_IFLNK, S_IFREG, S_IFSOCK};
 use prost_types::value::Kind;
 use prost_types::value::Kind::StructValue;
 use prost_types::{Struct, Value};
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
 use std::fs;
 use std::os::linux::fs::MetadataExt;
-use std::path::PathBuf;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Minimum time between progress reports
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Most results sent to the user in one part of the output
+const OUTPUT_BATCH: usize = 100;
+
+/// Most patterns a single brace expression may expand to
+const MAX_EXPANSIONS: usize = 256;
 
 struct FileType(u32);
 
@@ -19,21 +32,21 @@ impl FileType {
     }
 }
 
-impl Into<char> for FileType {
-    fn into(self) -> char {
-        if self.is(S_IFREG) {
+impl From<FileType> for char {
+    fn from(value: FileType) -> Self {
+        if value.is(S_IFREG) {
             '-'
-        } else if self.is(S_IFDIR) {
+        } else if value.is(S_IFDIR) {
             'd'
-        } else if self.is(S_IFLNK) {
+        } else if value.is(S_IFLNK) {
             'l'
-        } else if self.is(S_IFCHR) {
+        } else if value.is(S_IFCHR) {
             'c'
-        } else if self.is(S_IFBLK) {
+        } else if value.is(S_IFBLK) {
             'b'
-        } else if self.is(S_IFIFO) {
+        } else if value.is(S_IFIFO) {
             'p'
-        } else if self.is(S_IFSOCK) {
+        } else if value.is(S_IFSOCK) {
             's'
         } else {
             '?'
@@ -41,30 +54,156 @@ impl Into<char> for FileType {
     }
 }
 
-struct FileEntry {
-    path: String,
+#[derive(Clone)]
+pub(super) struct FileEntry {
+    pub(super) path: String,
     uid: u32,
     gid: u32,
-    mode: String,
+    pub(super) mode: u32,
+    /// Last access, in seconds since the Unix epoch
+    pub(super) atime: i64,
+    pub(super) size: u64,
 }
 
-impl Into<Struct> for FileEntry {
-    fn into(self) -> Struct {
-        Struct {
-            fields: BTreeMap::from([
-                ("path".to_string(), Value::from(self.path)),
-                ("uid".to_string(), Value::from(self.uid)),
-                ("gid".to_string(), Value::from(self.gid)),
-                ("mode".to_string(), Value::from(self.mode)),
-            ]),
+impl FileEntry {
+    pub(super) fn is_file(&self) -> bool {
+        FileType(self.mode).is(S_IFREG)
+    }
+}
+
+impl FileEntry {
+    /// With `verbose`, the mode is also given as octal digits and a file type character
+    fn into_struct(self, verbose: bool) -> Struct {
+        let mut fields = BTreeMap::from([
+            ("path".to_string(), Value::from(self.path)),
+            ("uid".to_string(), Value::from(self.uid)),
+            ("gid".to_string(), Value::from(self.gid)),
+            ("mode".to_string(), Value::from(mode_to_str(self.mode))),
+        ]);
+
+        if verbose {
+            let file_type = char::from(FileType(self.mode)).to_string();
+            let mode_octal = format!("{:04o}", self.mode & 0o7777);
+
+            fields.insert("file_type".to_string(), Value::from(file_type));
+            fields.insert("mode_octal".to_string(), Value::from(mode_octal));
+        }
+
+        Struct { fields }
+    }
+}
+
+pub(super) struct SearchOptions {
+    verbose: bool,
+    include_hidden: bool,
+    case_insensitive: bool,
+    literal_separator: bool,
+    count_only: bool,
+    canonicalize: bool,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            verbose: false,
+            include_hidden: false,
+            case_insensitive: false,
+            literal_separator: true,
+            count_only: false,
+            canonicalize: false,
         }
     }
 }
 
+impl SearchOptions {
+    fn from_args(args: &Struct) -> Result<Self, String> {
+        Ok(Self {
+            verbose: get_bool(args, "verbose", false)?,
+            include_hidden: get_bool(args, "include_hidden", false)?,
+            case_insensitive: get_bool(args, "case_insensitive", false)?,
+            literal_separator: get_bool(args, "literal_separator", true)?,
+            count_only: get_bool(args, "count_only", false)?,
+            canonicalize: get_bool(args, "canonicalize", false)?,
+        })
+    }
+
+    fn match_options(&self) -> MatchOptions {
+        MatchOptions {
+            case_sensitive: !self.case_insensitive,
+            require_literal_separator: self.literal_separator,
+            ..MatchOptions::new()
+        }
+    }
+}
+
+/// Walks everything under the pattern's literal directory prefix, for filtering by a pattern
+/// whose wildcards may cross `/`. `glob_with` matches one component at a time, so it can't,
+/// and it doesn't yield what `**` descends through without a match either.
+fn walk_pattern(pattern: &str) -> String {
+    let components: Vec<Component> = Path::new(pattern).components().collect();
+    let prefix: PathBuf = components
+        .iter()
+        .take(components.len().saturating_sub(1))
+        .take_while(|c| !has_wildcards(&c.as_os_str().to_string_lossy()))
+        .collect();
+
+    if prefix.as_os_str().is_empty() {
+        "**/*".to_string()
+    } else {
+        format!("{}/**/*", Pattern::escape(prefix.to_string_lossy().trim_end_matches('/')))
+    }
+}
+
+/// Whether hidden entries can be left out of the walk for `pattern` as they're found, rather
+/// than filtered out after descending into them: unless asked for, or named in the pattern
+/// past its literal directory prefix, none could match
+fn prunes_hidden(pattern: &str, options: &SearchOptions) -> bool {
+    let literal_prefix = Path::new(pattern)
+        .components()
+        .take_while(|c| !has_wildcards(&c.as_os_str().to_string_lossy()))
+        .count();
+    !options.include_hidden && !Path::new(pattern).components().skip(literal_prefix).any(|c| is_hidden(&c))
+}
+
+/// Whether the pattern is walked whole and filtered, rather than globbed directly
+fn needs_walk(pattern: &str, options: &SearchOptions) -> bool {
+    has_wildcards(pattern) && (!options.literal_separator || pattern.contains("**"))
+}
+
+fn has_wildcards(component: &str) -> bool {
+    component.contains(['*', '?', '['])
+}
+
+fn is_hidden(component: &Component) -> bool {
+    matches!(component, Component::Normal(name) if name.as_encoded_bytes().starts_with(b"."))
+}
+
+/// Whether `path` has a hidden component the pattern didn't name itself, i.e. one matched
+/// by a wildcard rather than in the literal directory prefix or as the literal last component
+fn is_hidden_match(path: &Path, pattern: &str) -> bool {
+    // `glob` drops a leading `./` from what it yields, so it's left out on both sides
+    let pattern = Path::new(pattern);
+    let literal_prefix = pattern
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .take_while(|c| !has_wildcards(&c.as_os_str().to_string_lossy()))
+        .count();
+    let hidden_last = pattern.components().next_back().is_some_and(|c| is_hidden(&c));
+
+    let components: Vec<Component> = path.components().filter(|c| *c != Component::CurDir).collect();
+    let last = components.len().saturating_sub(1);
+
+    components
+        .iter()
+        .enumerate()
+        .skip(literal_prefix)
+        .any(|(i, c)| is_hidden(c) && !(hidden_last && i == last))
+}
+
 fn mode_to_str(mode: u32) -> String {
     let mut v: [char; 10] = ['-'; 10];
 
-    v[0] = <FileType as Into<char>>::into(FileType(mode));
+    v[0] = char::from(FileType(mode));
 
     let tbl: [char; 9] = ['r', 'w', 'x', 'r', 'w', 'x', 'r', 'w', 'x'];
 
@@ -97,64 +236,320 @@ fn path_to_entry(path: PathBuf) -> Result<FileEntry, Box<dyn Error>> {
         path: path.to_string_lossy().to_string(),
         uid: metadata.st_uid(),
         gid: metadata.st_gid(),
-        mode: mode_to_str(metadata.st_mode()),
+        mode: metadata.st_mode(),
+        atime: metadata.st_atime(),
+        size: metadata.st_size(),
     })
 }
 
-fn search_fs(pattern: &str) -> (Vec<FileEntry>, Vec<String>) {
-    let mut entries: Vec<FileEntry> = vec![];
+fn respond_progress(scanned: usize, errors: usize) -> Struct {
+    Struct {
+        fields: BTreeMap::from([
+            ("scanned".to_string(), Value::from(scanned as f64)),
+            ("errors".to_string(), Value::from(errors as f64)),
+        ]),
+    }
+}
+
+/// Bytes of `pattern` outside `glob` character classes, with their indices, so `[{]` and
+/// `[,]` stand for themselves
+fn outside_classes(pattern: &str) -> Vec<(usize, u8)> {
+    let bytes = pattern.as_bytes();
+    let mut outside = vec![];
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'[' {
+            // A `]` right after `[` or `[!` is a member, not the end
+            let mut first = i + 1;
+            if bytes.get(first) == Some(&b'!') {
+                first += 1;
+            }
+            if bytes.get(first) == Some(&b']') {
+                first += 1;
+            }
+            if let Some(len) = bytes.get(first..).and_then(|rest| rest.iter().position(|&b| b == b']')) {
+                i = first + len + 1;
+                continue;
+            }
+        }
+        outside.push((i, bytes[i]));
+        i += 1;
+    }
+
+    outside
+}
+
+/// Byte range of the first `{...}` in `pattern` with alternatives, and where they split
+fn find_braces(pattern: &str) -> Option<(usize, usize, Vec<usize>)> {
+    let bytes = outside_classes(pattern);
+
+    for (n, &(start, _)) in bytes.iter().enumerate().filter(|(_, (_, b))| *b == b'{') {
+        let mut depth = 0;
+        let mut commas = vec![];
+
+        for &(i, b) in &bytes[n..] {
+            match b {
+                b'{' => depth += 1,
+                b',' if depth == 1 => commas.push(i),
+                b'}' => {
+                    depth -= 1;
+                    if depth == 0 {
+                        if !commas.is_empty() {
+                            return Some((start, i, commas));
+                        }
+                        break;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    None
+}
+
+/// Expands shell-style braces, e.g. `*.{rs,toml}` into `*.rs` and `*.toml`, which `glob`
+/// doesn't support. Braces without a comma or a match are left as they are, as are those
+/// escaped like `[{]`.
+fn expand_braces(pattern: &str) -> Result<Vec<String>, String> {
+    let Some((start, end, commas)) = find_braces(pattern) else {
+        return Ok(vec![pattern.to_string()]);
+    };
+
+    let (prefix, suffix) = (&pattern[..start], &pattern[end + 1..]);
+
+    let mut splits = vec![start];
+    splits.extend(commas);
+    splits.push(end);
+
+    let mut patterns = vec![];
+    for w in splits.windows(2) {
+        let alternative = &pattern[w[0] + 1..w[1]];
+        patterns.extend(expand_braces(&format!("{}{}{}", prefix, alternative, suffix))?);
+
+        if patterns.len() > MAX_EXPANSIONS {
+            return Err(format!(
+                "Pattern '{}' expands to more than {} patterns",
+                pattern, MAX_EXPANSIONS
+            ));
+        }
+    }
+
+    Ok(patterns)
+}
+
+/// Calls `visit` with each distinct path matching `pattern`; returns errors on the way
+fn walk(
+    pattern: &str,
+    options: &SearchOptions,
+    progress: &Progress,
+    visit: impl FnMut(PathBuf, &mut Vec<String>),
+) -> Vec<String> {
+    let max_entries = CONFIG.get().unwrap().max_walk_entries.unwrap_or(usize::MAX);
+    walk_limited(pattern, options, progress, max_entries, visit)
+}
+
+/// `walk`, stopping with an error once it has walked through `max_entries` paths
+fn walk_limited(
+    pattern: &str,
+    options: &SearchOptions,
+    progress: &Progress,
+    max_entries: usize,
+    mut visit: impl FnMut(PathBuf, &mut Vec<String>),
+) -> Vec<String> {
     let mut errors: Vec<String> = vec![];
 
-    let glob = match glob(pattern) {
-        Ok(glob) => glob,
+    let patterns = match expand_braces(pattern) {
+        Ok(patterns) => patterns,
         Err(e) => {
-            errors.push(e.to_string());
-            return (entries, errors);
+            errors.push(e);
+            return errors;
         }
     };
 
-    for entry in glob {
-        let Ok(path) = entry else {
-            continue;
+    let (mut globs, mut filters) = (vec![], vec![]);
+    for pattern in &patterns {
+        // Walked paths come without the leading `./` a pattern may have
+        let glob = if needs_walk(pattern, options) {
+            let filter = pattern.trim_start_matches("./");
+            let walk_options = MatchOptions {
+                require_literal_leading_dot: prunes_hidden(pattern, options),
+                ..options.match_options()
+            };
+            Pattern::new(filter).and_then(|filter| {
+                glob_with(&walk_pattern(pattern), walk_options).map(|glob| (glob, Some(filter)))
+            })
+        } else {
+            glob_with(pattern, options.match_options()).map(|glob| (glob, None))
         };
 
-        let entry = match path_to_entry(path) {
-            Ok(entry) => entry,
+        match glob {
+            Ok((glob, filter)) => {
+                globs.push((pattern, glob));
+                filters.push(filter);
+            }
             Err(e) => {
-                errors.push(e.to_string());
-                continue;
+                errors.push(format!(
+                    "Invalid pattern '{}' at byte {}: {}",
+                    pattern, e.pos, e.msg
+                ));
+                return errors;
             }
+        }
+    }
+
+    // Alternatives can overlap, e.g. `{src,src/bin}/**`; a single pattern yields no duplicates
+    // but for links, which only canonical paths reveal
+    let dedupe = patterns.len() > 1 || options.canonicalize;
+    let mut seen: HashSet<PathBuf> = HashSet::new();
+    let mut scanned = 0;
+    let mut last_report = Instant::now();
+
+    let match_options = options.match_options();
+    let entries_of = globs
+        .into_iter()
+        .enumerate()
+        .flat_map(|(i, (pattern, glob))| glob.map(move |entry| (i, pattern, entry)));
+
+    // Walked paths all count, matches or not, so the limit bounds the walk itself
+    for (i, pattern, entry) in entries_of {
+        scanned += 1;
+        if scanned > max_entries {
+            errors.push(format!(
+                "Too many entries: stopped after walking {} paths; narrow your pattern",
+                max_entries
+            ));
+            break;
+        }
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            progress.report(respond_progress(scanned, errors.len()));
+            last_report = Instant::now();
+        }
+
+        let Ok(path) = entry else {
+            continue;
         };
+        if filters[i].as_ref().is_some_and(|filter| !filter.matches_path_with(&path, match_options)) {
+            continue;
+        }
+        if !options.include_hidden && is_hidden_match(&path, pattern) {
+            continue;
+        }
+        let path = if options.canonicalize {
+            match fs::canonicalize(&path) {
+                Ok(real) => real,
+                Err(e) => {
+                    errors.push(format!("Cannot resolve '{}', kept as found: {}", path.display(), e));
+                    path
+                }
+            }
+        } else {
+            path
+        };
+        if dedupe && !seen.insert(path.clone()) {
+            continue;
+        }
 
-        entries.push(entry);
+        visit(path, &mut errors);
     }
 
+    errors
+}
+
+pub(super) fn search_fs(
+    pattern: &str,
+    options: &SearchOptions,
+    progress: &Progress,
+) -> (Vec<FileEntry>, Vec<String>) {
+    search_fs_with(pattern, options, progress, |_| {})
+}
+
+/// `search_fs`, calling `found` with each entry as it's looked up
+fn search_fs_with(
+    pattern: &str,
+    options: &SearchOptions,
+    progress: &Progress,
+    mut found: impl FnMut(&FileEntry),
+) -> (Vec<FileEntry>, Vec<String>) {
+    let mut entries: Vec<FileEntry> = vec![];
+
+    let errors = walk(pattern, options, progress, |path, errors| {
+        match path_to_entry(path) {
+            Ok(entry) => {
+                found(&entry);
+                entries.push(entry);
+            }
+            Err(e) => errors.push(e.to_string()),
+        }
+    });
+
     (entries, errors)
 }
 
-fn respond_error(errors: Vec<String>) -> Struct {
-    let errors: Vec<Value> = errors
+/// `search_fs`, sending results to the user as output in batches while it walks, so a long
+/// search shows what it found before it ends
+fn search_fs_streaming(
+    pattern: &str,
+    options: &SearchOptions,
+    progress: &Progress,
+) -> (Vec<FileEntry>, Vec<String>) {
+    let mut batch: Vec<Value> = vec![];
+    let mut last_output = Instant::now();
+
+    // What's left in the last batch comes with the response, which holds every result anyway
+    search_fs_with(pattern, options, progress, |entry| {
+        batch.push(Value::from(StructValue(entry.clone().into_struct(options.verbose))));
+
+        if batch.len() >= OUTPUT_BATCH || last_output.elapsed() >= PROGRESS_INTERVAL {
+            let results = std::mem::take(&mut batch);
+            progress.output(Struct {
+                fields: BTreeMap::from([("results".to_string(), Value::from(results))]),
+            });
+            last_output = Instant::now();
+        }
+    })
+}
+
+/// Number of paths `search_fs` would find, without looking any of them up
+fn count_fs(pattern: &str, options: &SearchOptions, progress: &Progress) -> (usize, Vec<String>) {
+    let mut count = 0;
+    let errors = walk(pattern, options, progress, |_, _| count += 1);
+    (count, errors)
+}
+
+/// `error` in the usual shape, also as the only entry of `errors` beside empty `results`
+fn respond_error(error: String) -> Struct {
+    let mut fields = Struct::from(ToolError::InvalidArgs(error.clone())).fields;
+    fields.insert("results".to_string(), Value::from(vec![]));
+    fields.insert("errors".to_string(), Value::from(vec![Value::from(error)]));
+
+    Struct { fields }
+}
+
+fn respond_count(count: usize, errors: Vec<String>) -> Struct {
+    let errors = errors
         .into_iter()
-        .map(|s| Value::from(s))
-        .collect();
+        .map(Value::from)
+        .collect::<Vec<Value>>();
 
     Struct {
         fields: BTreeMap::from([
-            ("results".to_string(), Value::from(vec![])),
+            ("count".to_string(), Value::from(count as f64)),
             ("errors".to_string(), Value::from(errors))
         ]),
     }
 }
 
-fn respond(success: Vec<FileEntry>, errors: Vec<String>) -> Struct {
+fn respond(success: Vec<FileEntry>, errors: Vec<String>, options: &SearchOptions) -> Struct {
     let success = success
         .into_iter()
-        .map(|entry| <FileEntry as Into<Struct>>::into(entry.into()))
+        .map(|entry| entry.into_struct(options.verbose))
         .map(|s| Value::from(StructValue(s)))
         .collect::<Vec<Value>>();
     let errors = errors
         .into_iter()
-        .map(|v| Value::from(v))
+        .map(Value::from)
         .collect::<Vec<Value>>();
 
     Struct {
@@ -165,14 +560,14 @@ fn respond(success: Vec<FileEntry>, errors: Vec<String>) -> Struct {
     }
 }
 
-pub fn handle_search_fs(call: FunctionCall) -> FunctionResponse {
+pub fn handle_search_fs(call: FunctionCall, progress: &Progress) -> FunctionResponse {
     assert_eq!(call.name, "search_fs");
 
     let Some(args) = call.args.as_ref() else {
         return FunctionResponse{
             id: call.id,
             name: call.name,
-            response: Some(respond_error(vec!["Argument is none".to_string()])),
+            response: Some(respond_error("Argument is none".to_string())),
         };
     };
 
@@ -180,7 +575,7 @@ pub fn handle_search_fs(call: FunctionCall) -> FunctionResponse {
         return FunctionResponse{
             id: call.id,
             name: call.name,
-            response: Some(respond_error(vec!["Required argument 'pattern' is missing".to_string()])),
+            response: Some(respond_error("Required argument 'pattern' is missing".to_string())),
         };
     };
 
@@ -188,7 +583,7 @@ pub fn handle_search_fs(call: FunctionCall) -> FunctionResponse {
         return FunctionResponse{
             id: call.id,
             name: call.name,
-            response: Some(respond_error(vec!["Required argument 'pattern' is null".to_string()])),
+            response: Some(respond_error("Required argument 'pattern' is null".to_string())),
         };
     };
 
@@ -198,17 +593,34 @@ pub fn handle_search_fs(call: FunctionCall) -> FunctionResponse {
             return FunctionResponse{
                 id: call.id,
                 name: call.name,
-                response: Some(respond_error(vec!["String argument 'pattern' is not a string".to_string()])),
+                response: Some(respond_error("String argument 'pattern' is not a string".to_string())),
             };
         }
     };
 
-    let (success, errors) = search_fs(pattern);
+    let options = match SearchOptions::from_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(respond_error(e)),
+            };
+        }
+    };
+
+    let resp = if options.count_only {
+        let (count, errors) = count_fs(pattern, &options, progress);
+        respond_count(count, errors)
+    } else {
+        let (success, errors) = search_fs_streaming(pattern, &options, progress);
+        respond(success, errors, &options)
+    };
 
     FunctionResponse{
         id: call.id,
         name: call.name,
-        response: Some(respond(success, errors)),
+        response: Some(resp),
     }
 }
 
@@ -223,26 +635,86 @@ pub fn search_fs_decl() -> FunctionDeclaration {
         ## Usage
 
         The glob expression syntax is same as standard UNIX glob expression syntax.
+        Braces are expanded as in shells, and results of the alternatives are merged; `[{]` matches a literal brace.
+        Hidden files and directories are skipped unless named in the pattern or `include_hidden` is set.
+        `*` and `?` stay within one path component unless `literal_separator` is set to false.
 
         ## Examples
 
         - `/repos/**/*.cxx` : Find `.cxx` file in `/repos` recursively
         - `/repos/*.h` : Find `.h` file in `/repos` not-recursively
+        - `/repos/**/*.{rs,toml}` : Find `.rs` and `.toml` files in `/repos` recursively
 
         "#
         .to_string(),
         parameters: Some(Schema {
             r#type: 6, /* OBJECT */
             nullable: false,
-            properties: HashMap::from([(
-                "pattern".to_string(),
-                Schema {
-                    r#type: 1, /* STRING */
-                    description: "Glob expression to search".to_string(),
-                    nullable: false,
-                    ..Schema::default()
-                },
-            )]),
+            properties: HashMap::from([
+                (
+                    "pattern".to_string(),
+                    Schema {
+                        r#type: 1, /* STRING */
+                        description: "Glob expression to search".to_string(),
+                        nullable: false,
+                        ..Schema::default()
+                    },
+                ),
+                (
+                    "case_insensitive".to_string(),
+                    Schema {
+                        r#type: 4, /* BOOLEAN */
+                        description: "Match letters regardless of case (e.g. `*.PNG` matches `photo.png`); false by default. Separators are unaffected".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
+                (
+                    "include_hidden".to_string(),
+                    Schema {
+                        r#type: 4, /* BOOLEAN */
+                        description: "Also return entries inside or named with a leading `.` (e.g. `.git`, `.env`) where they're matched by a wildcard; false by default".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
+                (
+                    "literal_separator".to_string(),
+                    Schema {
+                        r#type: 4, /* BOOLEAN */
+                        description: "Whether `*` and `?` must stop at `/`; true by default. When false, `src/*.rs` also matches `src/bin/main.rs`, searching everything under the leading directories without wildcards".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
+                (
+                    "count_only".to_string(),
+                    Schema {
+                        r#type: 4, /* BOOLEAN */
+                        description: "Return only `count` of matches instead of `results`, which is much faster on large trees; false by default".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
+                (
+                    "canonicalize".to_string(),
+                    Schema {
+                        r#type: 4, /* BOOLEAN */
+                        description: "Return each result by its real path, with links and `.`/`..` resolved, and drop results that turn out to be the same file; false by default. A path that can't be resolved (e.g. a broken link) is kept as found, with a note in `errors`".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
+                (
+                    "verbose".to_string(),
+                    Schema {
+                        r#type: 4, /* BOOLEAN */
+                        description: "Also return `mode_octal` (e.g. \"0755\") and `file_type` (e.g. \"d\") for each result".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
+            ]),
             required: vec!["pattern".to_string()],
             ..Schema::default()
         }),
@@ -264,6 +736,25 @@ pub fn search_fs_decl() -> FunctionDeclaration {
                         ..Schema::default()
                     },
                 ),
+                (
+                    "error".to_string(),
+                    Schema {
+                        r#type: 1, /* STRING */
+                        description: "(Optional) Why the search couldn't start, also given in `errors`".to_string(),
+                        nullable: false,
+                        ..Schema::default()
+                    },
+                ),
+                ("error_kind".to_string(), error_kind_schema()),
+                (
+                    "count".to_string(),
+                    Schema {
+                        r#type: 3, /* INTEGER */
+                        description: "Number of matches, in place of `results` if `count_only` was set".to_string(),
+                        nullable: true,
+                        ..Schema::default()
+                    },
+                ),
                 (
                     "results".to_string(),
                     Schema {
@@ -307,6 +798,24 @@ pub fn search_fs_decl() -> FunctionDeclaration {
                                         ..Schema::default()
                                     },
                                 ),
+                                (
+                                    "mode_octal".to_string(),
+                                    Schema {
+                                        r#type: 1, /* STRING */
+                                        description: "Permission bits as 4 octal digits; only if `verbose`".to_string(),
+                                        nullable: false,
+                                        ..Schema::default()
+                                    },
+                                ),
+                                (
+                                    "file_type".to_string(),
+                                    Schema {
+                                        r#type: 1, /* STRING */
+                                        description: "File type character as in `ls -l`; only if `verbose`".to_string(),
+                                        nullable: false,
+                                        ..Schema::default()
+                                    },
+                                ),
                             ]),
                             required: vec![
                                 "path".to_string(),
@@ -327,3 +836,89 @@ pub fn search_fs_decl() -> FunctionDeclaration {
         }),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn walked(pattern: &str, max_entries: usize) -> (Vec<String>, Vec<String>) {
+        let mut paths = vec![];
+        let errors = walk_limited(pattern, &SearchOptions::default(), &Progress::none(), max_entries, |path, _| {
+            paths.push(path.to_string_lossy().to_string())
+        });
+        paths.sort();
+        (paths, errors)
+    }
+
+    #[test]
+    fn braces_expand_in_order_and_nest() {
+        assert_eq!(expand_braces("*.{rs,toml}").unwrap(), ["*.rs", "*.toml"]);
+        assert_eq!(expand_braces("{a,b{c,d}}/x").unwrap(), ["a/x", "bc/x", "bd/x"]);
+        assert_eq!(expand_braces("{a,}{1,2}").unwrap(), ["a1", "a2", "1", "2"]);
+    }
+
+    #[test]
+    fn escaped_or_unbalanced_braces_stay() {
+        assert_eq!(expand_braces("[{]a,b}").unwrap(), ["[{]a,b}"]);
+        assert_eq!(expand_braces("{a[,]b}").unwrap(), ["{a[,]b}"]);
+        assert_eq!(expand_braces("[]{]{a,b}").unwrap(), ["[]{]a", "[]{]b"]);
+        assert_eq!(expand_braces("{none}").unwrap(), ["{none}"]);
+        assert_eq!(expand_braces("{a,b").unwrap(), ["{a,b"]);
+        assert_eq!(expand_braces("a,b}").unwrap(), ["a,b}"]);
+        assert_eq!(expand_braces("{a,{b,c}").unwrap(), ["{a,b", "{a,c"]);
+    }
+
+    #[test]
+    fn braces_expand_to_at_most_max_expansions() {
+        assert_eq!(expand_braces(&"{a,b}".repeat(8)).unwrap().len(), MAX_EXPANSIONS);
+        assert!(expand_braces(&"{a,b}".repeat(9)).is_err());
+    }
+
+    #[test]
+    fn recursive_pattern_matches_at_every_depth() {
+        let dir = temp_dir("search-recursive");
+        for path in ["a.rs", "c.txt", "sub/b.rs", ".hidden/d.rs"] {
+            let path = dir.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "").unwrap();
+        }
+
+        let (paths, errors) = walked(&format!("{}/**/*.rs", dir.display()), usize::MAX);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(paths, [
+            dir.join("a.rs").to_string_lossy(),
+            dir.join("sub/b.rs").to_string_lossy(),
+        ]);
+    }
+
+    #[test]
+    fn hidden_trees_are_not_walked() {
+        let dir = temp_dir("search-prune");
+        fs::create_dir_all(dir.join(".git/objects")).unwrap();
+        for i in 0..20 {
+            fs::write(dir.join(format!(".git/objects/{}", i)), "").unwrap();
+        }
+        fs::write(dir.join("config.toml"), "").unwrap();
+
+        let (paths, errors) = walked(&format!("{}/**/*config*", dir.display()), 5);
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(paths, [dir.join("config.toml").to_string_lossy()]);
+
+        // Named in the pattern, they are
+        let (paths, _) = walked(&format!("{}/**/.git", dir.display()), usize::MAX);
+        assert_eq!(paths, [dir.join(".git").to_string_lossy()]);
+    }
+
+    #[test]
+    fn limit_counts_paths_walked_without_a_match() {
+        let dir = temp_dir("search-limit");
+        for i in 0..10 {
+            fs::write(dir.join(format!("{}.txt", i)), "").unwrap();
+        }
+
+        let (paths, errors) = walked(&format!("{}/**/missing.rs", dir.display()), 5);
+        assert!(paths.is_empty());
+        assert_eq!(errors, ["Too many entries: stopped after walking 5 paths; narrow your pattern"]);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::tools::args::get_string;
+use crate::tools::search_fs::{search_fs, SearchOptions};
+use crate::tools::{error_kind_schema, Progress, ToolError};
+use google_ai_rs::proto::{FunctionCall, FunctionDeclaration, FunctionResponse};
+use google_ai_rs::Schema;
+use prost_types::value::Kind;
+use prost_types::{Struct, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::Read;
+
+/// Most files counted for a glob; the rest are left out of the rows and the total
+const MAX_FILES: usize = 1000;
+
+#[derive(Default)]
+struct Counts {
+    lines: u64,
+    words: u64,
+    bytes: u64,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.lines += other.lines;
+        self.words += other.words;
+        self.bytes += other.bytes;
+    }
+
+    fn into_fields(self) -> BTreeMap<String, Value> {
+        BTreeMap::from([
+            ("lines".to_string(), Value::from(self.lines as f64)),
+            ("words".to_string(), Value::from(self.words as f64)),
+            ("bytes".to_string(), Value::from(self.bytes as f64)),
+        ])
+    }
+}
+
+/// Counts as `wc` does: newlines, runs of non-whitespace and bytes, a chunk at a time
+fn wc_fs(path: &str) -> Result<Counts, String> {
+    let mut file = File::open(path).map_err(|e| format!("Cannot open '{}': {}", path, e))?;
+
+    let mut counts = Counts::default();
+    let mut in_word = false;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file
+            .read(&mut buf)
+            .map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+        if n == 0 {
+            return Ok(counts);
+        }
+
+        counts.bytes += n as u64;
+        for &b in &buf[..n] {
+            if b == b'\n' {
+                counts.lines += 1;
+            }
+            if b.is_ascii_whitespace() {
+                in_word = false;
+            } else if !in_word {
+                in_word = true;
+                counts.words += 1;
+            }
+        }
+    }
+}
+
+fn has_wildcards(s: &str) -> bool {
+    s.contains(['*', '?', '[', '{'])
+}
+
+fn wc_glob(pattern: &str, progress: &Progress) -> Struct {
+    let (entries, mut errors) = search_fs(pattern, &SearchOptions::default(), progress);
+    let paths: Vec<String> = entries
+        .into_iter()
+        .filter(|entry| entry.is_file())
+        .map(|entry| entry.path)
+        .collect();
+    let truncated = paths.len() > MAX_FILES;
+
+    let mut total = Counts::default();
+    let mut files = vec![];
+    for path in paths.into_iter().take(MAX_FILES) {
+        match wc_fs(&path) {
+            Ok(counts) => {
+                total.add(&counts);
+                let mut fields = counts.into_fields();
+                fields.insert("path".to_string(), Value::from(path));
+                files.push(Value::from(Kind::StructValue(Struct { fields })));
+            }
+            Err(e) => errors.push(e),
+        }
+    }
+
+    let errors: Vec<Value> = errors.into_iter().map(Value::from).collect();
+    let mut fields = BTreeMap::from([
+        ("files".to_string(), Value::from(files)),
+        ("total".to_string(), Value::from(Kind::StructValue(Struct { fields: total.into_fields() }))),
+        ("errors".to_string(), Value::from(errors)),
+    ]);
+    if truncated {
+        fields.insert("truncated".to_string(), Value::from(true));
+    }
+
+    Struct { fields }
+}
+
+pub fn handle_wc_fs(call: FunctionCall, progress: &Progress) -> FunctionResponse {
+    assert_eq!(call.name, "wc_fs");
+
+    let Some(args) = call.args.as_ref() else {
+        return FunctionResponse{
+            id: call.id,
+            name: call.name,
+            response: Some(ToolError::InvalidArgs("Argument is none".to_string()).into()),
+        };
+    };
+
+    let path = match get_string(args, "path") {
+        Ok(path) => path,
+        Err(e) => {
+            return FunctionResponse{
+                id: call.id,
+                name: call.name,
+                response: Some(ToolError::InvalidArgs(e).into()),
+            };
+        }
+    };
+
+    let resp = if has_wildcards(path) {
+        wc_glob(path, progress)
+    } else {
+        match wc_fs(path) {
+            Ok(counts) => Struct { fields: counts.into_fields() },
+            Err(e) => ToolError::ExecutionFailed(e).into(),
+        }
+    };
+
+    FunctionResponse{
+        id: call.id,
+        name: call.name,
+        response: Some(resp),
+    }
+}
+
+fn counts_properties() -> HashMap<String, Schema> {
+    HashMap::from([
+        ("lines".to_string(), Schema{
+            r#type: 3, /* INTEGER */
+            description: "Number of line breaks".to_string(),
+            nullable: false,
+            ..Schema::default()
+        }),
+        ("words".to_string(), Schema{
+            r#type: 3, /* INTEGER */
+            description: "Number of whitespace-separated words".to_string(),
+            nullable: false,
+            ..Schema::default()
+        }),
+        ("bytes".to_string(), Schema{
+            r#type: 3, /* INTEGER */
+            description: "Size in bytes".to_string(),
+            nullable: false,
+            ..Schema::default()
+        }),
+    ])
+}
+
+pub fn wc_fs_decl() -> FunctionDeclaration {
+    let mut file_properties = counts_properties();
+    file_properties.insert("path".to_string(), Schema {
+        r#type: 1, /* STRING */
+        nullable: false,
+        ..Schema::default()
+    });
+
+    let mut properties = counts_properties();
+    properties.extend([
+        ("error".to_string(), Schema{
+            r#type: 1, /* STRING */
+            description: "(Optional) Error during count".to_string(),
+            nullable: false,
+            ..Schema::default()
+        }),
+        ("error_kind".to_string(), error_kind_schema()),
+        ("files".to_string(), Schema{
+            r#type: 5, /* ARRAY */
+            description: "(Optional) Counts of each file, for a glob expression".to_string(),
+            nullable: false,
+            items: Some(Box::new(Schema {
+                r#type: 6, /* OBJECT */
+                nullable: false,
+                properties: file_properties,
+                ..Schema::default()
+            })),
+            ..Schema::default()
+        }),
+        ("total".to_string(), Schema{
+            r#type: 6, /* OBJECT */
+            description: "(Optional) Counts of all files together, for a glob expression".to_string(),
+            nullable: false,
+            properties: counts_properties(),
+            ..Schema::default()
+        }),
+        ("errors".to_string(), Schema{
+            r#type: 5, /* ARRAY */
+            description: "(Optional) Files that couldn't be counted, for a glob expression".to_string(),
+            nullable: false,
+            items: Some(Box::new(Schema {
+                r#type: 1, /* STRING */
+                nullable: false,
+                ..Schema::default()
+            })),
+            ..Schema::default()
+        }),
+        ("truncated".to_string(), Schema{
+            r#type: 4, /* BOOLEAN */
+            description: format!("(Optional) Set if the glob matched more than {} files and only those were counted", MAX_FILES),
+            nullable: false,
+            ..Schema::default()
+        }),
+    ]);
+
+    FunctionDeclaration {
+        name: "wc_fs".to_string(),
+        description: r#"
+        Count lines, words and bytes of a file on user's filesystem, like `wc`, without reading its content.
+        Useful to judge whether a file is worth reading in full.
+        Given a glob expression (e.g. `src/**/*.rs`), counts each matching file, with a total.
+        "#
+        .to_string(),
+        parameters: Some(Schema {
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties: HashMap::from([
+                ("path".to_string(), Schema {
+                    r#type: 1, /* STRING */
+                    description: "Path of file to count, or glob expression matching files".to_string(),
+                    nullable: false,
+                    ..Schema::default()
+                }),
+            ]),
+            required: vec!["path".to_string()],
+            ..Schema::default()
+        }),
+        response: Some(Schema{
+            r#type: 6, /* OBJECT */
+            nullable: false,
+            properties,
+            ..Schema::default()
+        }),
+    }
+}
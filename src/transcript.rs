@@ -0,0 +1,105 @@
+use crate::defs::*;
+use std::fmt::Write;
+
+fn render_struct(v: &Option<Struct>) -> String {
+    match v {
+        Some(v) => serde_json::to_string(v).unwrap(),
+        None => "null".to_string(),
+    }
+}
+
+fn end_line(out: &mut String) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+/// How much of the tool calls and their responses a transcript shows
+#[derive(Clone, Copy, PartialEq)]
+pub enum ToolDetail {
+    /// Calls with their arguments and responses in full
+    Full,
+    /// One line naming the tool for each call; responses are left out
+    Summary,
+    /// Neither calls nor responses
+    Omit,
+}
+
+impl ToolDetail {
+    pub fn parse(s: &str) -> Option<ToolDetail> {
+        match s {
+            "full" => Some(ToolDetail::Full),
+            "summary" => Some(ToolDetail::Summary),
+            "omit" => Some(ToolDetail::Omit),
+            _ => None,
+        }
+    }
+
+    fn shows(self, data: &Data) -> bool {
+        match data {
+            Data::FunctionCall(_) => self != ToolDetail::Omit,
+            Data::FunctionResponse(_) => self == ToolDetail::Full,
+            _ => true,
+        }
+    }
+}
+
+fn render_data(out: &mut String, data: &Data, tools: ToolDetail) {
+    // Streamed text arrives in chunks, so text is concatenated as-is
+    // and only the other kinds of data get lines of their own
+    if let Data::Text { text } = data {
+        out.push_str(text);
+        return;
+    }
+
+    end_line(out);
+    let _ = match data {
+        Data::FunctionCall(call) if tools == ToolDetail::Summary => {
+            writeln!(out, "> called {}", call.name)
+        }
+        Data::FunctionCall(call) => {
+            writeln!(out, "> call {}({})", call.name, render_struct(&call.args))
+        }
+        Data::FunctionResponse(resp) => {
+            writeln!(out, "> {} returned {}", resp.name, render_struct(&resp.response))
+        }
+        v => writeln!(out, "{}", serde_json::to_string(v).unwrap()),
+    };
+}
+
+/// Flattens contents into a human-readable plain-text transcript.
+///
+/// Consecutive contents of the same role (e.g. streamed model chunks) are merged under one header.
+/// Contents left with nothing to show under `tools`, such as tool responses in a summary, are skipped.
+pub fn render(contents: &[Content], tools: ToolDetail) -> String {
+    let mut out = String::new();
+    let mut last_role: Option<Role> = None;
+
+    for content in contents {
+        let shown: Vec<&Data> = content
+            .parts
+            .iter()
+            .filter_map(|part| part.data.as_ref())
+            .filter(|data| tools.shows(data))
+            .collect();
+        if shown.is_empty() {
+            continue;
+        }
+
+        if last_role != Some(content.role) {
+            end_line(&mut out);
+            if last_role.is_some() {
+                out.push('\n');
+            }
+            let _ = writeln!(out, "[{}]", content.role);
+            last_role = Some(content.role);
+        }
+
+        for data in shown {
+            render_data(&mut out, data, tools);
+        }
+    }
+
+    end_line(&mut out);
+    out
+}
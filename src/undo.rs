@@ -0,0 +1,159 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Maximum number of operations kept
+const MAX_ENTRIES: usize = 32;
+/// Files larger than this are not snapshotted, making the operation non-undoable, and with it
+/// every operation before
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+/// Maximum bytes of snapshots kept; the oldest are dropped beyond this
+const MAX_TOTAL_BYTES: usize = 16 * 1024 * 1024;
+
+static LOG: Mutex<UndoLog> = Mutex::new(UndoLog::new());
+
+enum Prior {
+    /// File didn't exist before the operation
+    Absent,
+    Content(Vec<u8>),
+    /// Not snapshotted; operations before can't be undone either, as reverting them would
+    /// silently drop this one
+    Unavailable,
+}
+
+struct Entry {
+    operation: &'static str,
+    path: PathBuf,
+    prior: Prior,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        match &self.prior {
+            Prior::Absent | Prior::Unavailable => 0,
+            Prior::Content(v) => v.len(),
+        }
+    }
+}
+
+struct UndoLog {
+    entries: VecDeque<Entry>,
+    total_bytes: usize,
+}
+
+impl UndoLog {
+    const fn new() -> Self {
+        Self {
+            entries: VecDeque::new(),
+            total_bytes: 0,
+        }
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.total_bytes += entry.size();
+        self.entries.push_back(entry);
+
+        while self.entries.len() > MAX_ENTRIES || self.total_bytes > MAX_TOTAL_BYTES {
+            let Some(oldest) = self.entries.pop_front() else {
+                break;
+            };
+            self.total_bytes -= oldest.size();
+        }
+    }
+
+    /// Forgets everything before an operation that can't be undone, which stays as a barrier
+    fn push_barrier(&mut self, operation: &'static str, path: &Path) {
+        self.entries.clear();
+        self.total_bytes = 0;
+        self.entries.push_back(Entry {
+            operation,
+            path: path.to_path_buf(),
+            prior: Prior::Unavailable,
+        });
+    }
+
+    /// The latest entry, taken off the log unless it's a barrier
+    fn pop(&mut self) -> Option<Entry> {
+        let entry = self.entries.back()?;
+        if let Prior::Unavailable = entry.prior {
+            return Some(Entry {
+                operation: entry.operation,
+                path: entry.path.clone(),
+                prior: Prior::Unavailable,
+            });
+        }
+
+        let entry = self.entries.pop_back()?;
+        self.total_bytes -= entry.size();
+        Some(entry)
+    }
+}
+
+/// Snapshots `path` before `operation` mutates it.
+///
+/// Returns whether the operation can be undone; files over the size cap are not snapshotted,
+/// and undo stops at them from then on.
+pub fn snapshot(operation: &'static str, path: impl AsRef<Path>) -> std::io::Result<bool> {
+    let path = path.as_ref();
+
+    let prior = match fs::metadata(path) {
+        Ok(metadata) if metadata.len() > MAX_FILE_BYTES => {
+            LOG.lock().unwrap().push_barrier(operation, path);
+            return Ok(false);
+        }
+        Ok(_) => Prior::Content(fs::read(path)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Prior::Absent,
+        Err(e) => return Err(e),
+    };
+
+    LOG.lock().unwrap().push(Entry {
+        operation,
+        path: path.to_path_buf(),
+        prior,
+    });
+
+    Ok(true)
+}
+
+#[derive(Serialize)]
+pub struct Undone {
+    pub operation: &'static str,
+    pub path: String,
+    /// Whether the file was removed because it didn't exist before the operation
+    pub removed: bool,
+}
+
+/// Why nothing was undone
+pub enum UndoError {
+    /// The log is empty
+    Nothing,
+    /// The latest operation wasn't snapshotted, so neither it nor any before can be undone
+    NotUndoable { operation: &'static str, path: String },
+    Io(std::io::Error),
+}
+
+/// Reverts the most recent operation
+pub fn undo() -> Result<Undone, UndoError> {
+    let entry = LOG.lock().unwrap().pop().ok_or(UndoError::Nothing)?;
+
+    let result = match &entry.prior {
+        Prior::Absent => fs::remove_file(&entry.path),
+        Prior::Content(v) => fs::write(&entry.path, v),
+        Prior::Unavailable => {
+            return Err(UndoError::NotUndoable {
+                operation: entry.operation,
+                path: entry.path.to_string_lossy().to_string(),
+            });
+        }
+    };
+
+    result.map_err(UndoError::Io)?;
+    Ok(Undone {
+        operation: entry.operation,
+        path: entry.path.to_string_lossy().to_string(),
+        removed: matches!(entry.prior, Prior::Absent),
+    })
+}
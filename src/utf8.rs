@@ -0,0 +1,78 @@
+/// Decodes UTF-8 arriving in arbitrary chunks, holding back a character split across
+/// chunks until the rest of it arrives.
+///
+/// Output read from a pipe comes in chunks cut wherever the read ended, often mid-character.
+#[derive(Default)]
+pub struct Utf8Buffer {
+    pending: Vec<u8>,
+}
+
+impl Utf8Buffer {
+    /// Complete characters decoded so far; invalid sequences become U+FFFD
+    pub fn push(&mut self, chunk: &[u8]) -> String {
+        self.pending.extend_from_slice(chunk);
+
+        let mut text = String::new();
+        loop {
+            match std::str::from_utf8(&self.pending) {
+                Ok(s) => {
+                    text.push_str(s);
+                    self.pending.clear();
+                    return text;
+                }
+                Err(e) => {
+                    let valid = e.valid_up_to();
+                    text.push_str(std::str::from_utf8(&self.pending[..valid]).unwrap());
+
+                    match e.error_len() {
+                        // Incomplete character at the end; wait for the next chunk
+                        None => {
+                            self.pending.drain(..valid);
+                            return text;
+                        }
+                        Some(len) => {
+                            text.push(char::REPLACEMENT_CHARACTER);
+                            self.pending.drain(..valid + len);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whatever is left once the stream ends, incomplete characters included
+    pub fn finish(&mut self) -> String {
+        let rest = String::from_utf8_lossy(&self.pending).to_string();
+        self.pending.clear();
+        rest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_character_comes_out_whole_once() {
+        let bytes = "a😀b".as_bytes();
+        let mut buffer = Utf8Buffer::default();
+
+        // The emoji is 4 bytes, starting at 1; cut it after its second byte
+        let first = buffer.push(&bytes[..3]);
+        let second = buffer.push(&bytes[3..]);
+        let rest = buffer.finish();
+
+        assert_eq!(first, "a");
+        assert_eq!(second, "😀b");
+        assert_eq!(rest, "");
+        assert_eq!(format!("{}{}{}", first, second, rest).matches('😀').count(), 1);
+    }
+
+    #[test]
+    fn invalid_bytes_become_replacement_characters() {
+        let mut buffer = Utf8Buffer::default();
+        assert_eq!(buffer.push(b"a\xffb"), "a\u{fffd}b");
+        assert_eq!(buffer.push(b"\xe2\x82"), "");
+        assert_eq!(buffer.finish(), "\u{fffd}");
+    }
+}
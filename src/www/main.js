@@ -5,6 +5,12 @@ document.addEventListener('DOMContentLoaded', () => {
     const chatForm = document.getElementById('chat-form');
     const chatInput = document.getElementById('chat-input');
     const sendButton = chatForm.querySelector('button');
+    const chatStatus = document.getElementById('chat-status');
+
+    const setStatus = (text) => {
+        chatStatus.textContent = text || '';
+        chatStatus.hidden = !text;
+    };
 
     const htmlEncode = (input) => {
         return input
@@ -36,13 +42,30 @@ document.addEventListener('DOMContentLoaded', () => {
                         <pre><code>${htmlEncode(JSON.stringify(part.args, null, 2))}</code></pre>
                     </details>
                 `;
-            case 'function_response':
+            case 'function_response': {
+                const handle = part.response && part.response.handle;
+                const display = part.response && part.response.display;
+                if (display && display.html) {
+                    const { display: _, ...response } = part.response;
+                    return `
+                        <pre class="highlight"><code>${display.html}</code></pre>
+                        <details class="accordion">
+                            <summary>Function Response: ${part.name}</summary>
+                            <pre><code>${htmlEncode(JSON.stringify(response, null, 2))}</code></pre>
+                        </details>
+                    `;
+                }
+                const loadFull = handle
+                    ? `<button class="load-full" data-handle="${htmlEncode(handle)}">Load full result</button>`
+                    : '';
                 return `
                     <details class="accordion">
                         <summary>Function Response: ${part.name}</summary>
                         <pre><code>${htmlEncode(JSON.stringify(part.response, null, 2))}</code></pre>
+                        ${loadFull}
                     </details>
                 `;
+            }
             default:
                 return `<pre><code>${htmlEncode(JSON.stringify(part, null, 2))}</code></pre>`;
         }
@@ -100,9 +123,42 @@ document.addEventListener('DOMContentLoaded', () => {
         scrollToBottom();
     };
 
+    const showAlternatives = (alternatives) => {
+        Object.entries(alternatives).forEach(([index, text]) => {
+            const messageDiv = document.createElement('div');
+            messageDiv.classList.add('message', 'model', 'alternative');
+            const roleDiv = document.createElement('div');
+            roleDiv.classList.add('role');
+            roleDiv.textContent = `alternative ${index}`;
+            const contentDiv = document.createElement('div');
+            contentDiv.classList.add('content');
+            contentDiv.innerHTML = marked.parse(text);
+            const chooseButton = document.createElement('button');
+            chooseButton.textContent = 'Use this reply';
+            chooseButton.addEventListener('click', async () => {
+                try {
+                    const response = await fetch('chat/choose', {
+                        method: 'POST',
+                        headers: { 'Content-Type': 'application/json' },
+                        body: JSON.stringify({ index: Number(index) })
+                    });
+                    if (!response.ok) throw new Error(`HTTP error! Status: ${response.status}`);
+                    loadHistory();
+                } catch (err) {
+                    console.error('Failed to choose alternative:', err);
+                }
+            });
+            messageDiv.appendChild(roleDiv);
+            messageDiv.appendChild(contentDiv);
+            messageDiv.appendChild(chooseButton);
+            chatLog.appendChild(messageDiv);
+        });
+        scrollToBottom();
+    };
+
     const loadHistory = async () => {
         try {
-            const response = await fetch('/chat');
+            const response = await fetch('chat');
             if (!response.ok) throw new Error(`HTTP error! Status: ${response.status}`);
             const history = await response.json();
             chatLog.innerHTML = '';
@@ -141,16 +197,18 @@ document.addEventListener('DOMContentLoaded', () => {
         chatInput.disabled = true;
         sendButton.disabled = true;
 
-        const sse = new SSE('/chat', {
+        const sse = new SSE('chat', {
             method: 'POST',
             headers: { 'Content-Type': 'application/json' },
             payload: JSON.stringify(userMessage)
         });
+        const alternatives = {};
 
         sse.addEventListener('message', (e) => {
             if (e.data) {
                 try {
                     const message = JSON.parse(e.data);
+                    setStatus('');
                     addOrUpdateMessage(message);
                 } catch (err) {
                     console.error('Failed to parse SSE message data:', e.data, err);
@@ -158,6 +216,73 @@ document.addEventListener('DOMContentLoaded', () => {
             }
         });
 
+        sse.addEventListener('candidate', (e) => {
+            try {
+                const { index, content } = JSON.parse(e.data);
+                alternatives[index] = (alternatives[index] || '') + getTextFromParts(content.parts);
+            } catch (err) {
+                console.error('Failed to parse SSE candidate data:', e.data, err);
+            }
+        });
+
+        sse.addEventListener('throttled', (e) => {
+            try {
+                const { retry_in_ms } = JSON.parse(e.data);
+                setStatus(`Rate limited by Gemini — retrying in ${Math.ceil(retry_in_ms / 1000)}s…`);
+            } catch (err) {
+                console.error('Failed to parse SSE throttled data:', e.data, err);
+            }
+        });
+
+        sse.addEventListener('approval_request', (e) => {
+            try {
+                const { id, name, args } = JSON.parse(e.data);
+                const approved = window.confirm(`Allow ${name}?\n\n${JSON.stringify(args ?? {}, null, 2)}`);
+                fetch('chat/approve', {
+                    method: 'POST',
+                    headers: { 'Content-Type': 'application/json' },
+                    body: JSON.stringify({ id, approved })
+                }).catch((err) => console.error('Failed to send approval:', err));
+            } catch (err) {
+                console.error('Failed to parse SSE approval_request data:', e.data, err);
+            }
+        });
+
+        sse.addEventListener('tool_started', (e) => {
+            try {
+                const { name } = JSON.parse(e.data);
+                setStatus(`Running ${name}…`);
+            } catch (err) {
+                console.error('Failed to parse SSE tool_started data:', e.data, err);
+            }
+        });
+
+        sse.addEventListener('progress', (e) => {
+            try {
+                const { name, progress } = JSON.parse(e.data);
+                const details = Object.entries(progress)
+                    .map(([key, value]) => `${key}: ${value}`)
+                    .join(', ');
+                setStatus(`${name} — ${details}`);
+            } catch (err) {
+                console.error('Failed to parse SSE progress data:', e.data, err);
+            }
+        });
+
+        const outputCounts = {};
+        sse.addEventListener('tool_output', (e) => {
+            try {
+                const { id, name, output } = JSON.parse(e.data);
+                const results = output.results || [];
+                outputCounts[id] = (outputCounts[id] || 0) + results.length;
+                const last = results.length ? results[results.length - 1] : null;
+                const latest = last && last.path ? `, latest: ${last.path}` : '';
+                setStatus(`${name} — ${outputCounts[id]} results so far${latest}`);
+            } catch (err) {
+                console.error('Failed to parse SSE tool_output data:', e.data, err);
+            }
+        });
+
         sse.addEventListener('error', (e) => {
             console.error('SSE Error:', e);
             const errorMessage = {
@@ -166,6 +291,7 @@ document.addEventListener('DOMContentLoaded', () => {
             };
             appendMessage(errorMessage);
             sse.close();
+            setStatus('');
             chatInput.disabled = false;
             sendButton.disabled = false;
         });
@@ -173,6 +299,8 @@ document.addEventListener('DOMContentLoaded', () => {
         sse.addEventListener('readystatechange', (e) => {
             if (e.readyState === SSE.CLOSED) {
                 console.log('SSE Stream finished and closed.');
+                setStatus('');
+                showAlternatives(alternatives);
                 chatInput.disabled = false;
                 sendButton.disabled = false;
                 chatInput.focus();
@@ -182,6 +310,21 @@ document.addEventListener('DOMContentLoaded', () => {
         sse.stream();
     };
 
+    chatLog.addEventListener('click', async (e) => {
+        const button = e.target.closest('.load-full');
+        if (!button) return;
+        try {
+            const response = await fetch(`chat/tool-result/${encodeURIComponent(button.dataset.handle)}`);
+            if (!response.ok) throw new Error(`HTTP error! Status: ${response.status}`);
+            const full = await response.json();
+            button.previousElementSibling.querySelector('code').textContent =
+                JSON.stringify(full.response, null, 2);
+            button.remove();
+        } catch (err) {
+            console.error('Failed to load full tool result:', err);
+        }
+    });
+
     chatForm.addEventListener('submit', handleFormSubmit);
 
     chatInput.addEventListener('keydown', (e) => {